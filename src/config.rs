@@ -0,0 +1,933 @@
+use fts::Field;
+use log::LevelFilter;
+use num_cpus;
+use scorer::ScorerKind;
+use serde_json;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use toml;
+
+pub const USAGE: &str = "Usage: marian-rust [options] <dir|bucket>:<...>
+       marian-rust eval [eval-options] <queries.json> [<dir|bucket>:<...>]
+
+Options:
+    -c, --config <path>      Load settings from a TOML or JSON file
+    -l, --listen <address>   Address to listen on [default: 127.0.0.1:3000]
+    -w, --workers <n>        Number of worker threads [default: number of CPUs]
+        --log-level <level>  One of off, error, warn, info, debug, trace [default: info]
+        --snapshot <path>    Save the index to this file, and load it at startup
+        --pinned <path>      Pin results for particular queries, as listed in this file
+        --refresh-interval <seconds>
+                             Periodically reload changed manifests
+        --watch              Reload as soon as files in a dir: source change
+        --max-results <n>    Most results to return per search [default: 150]
+    -h, --help               Print this message
+
+Eval options:
+    -c, --config <path>      Evaluate the ranking from this configuration file
+        --compare <path>     Compare against the ranking from a second configuration file
+    -k <n>                   Evaluate the top n results [default: 10]
+        --search-property <property>
+                             Search only this property; may be repeated
+
+Settings given on the command line override those in the configuration file.";
+
+/// HITS converges well before this, so more iterations only waste time.
+const MAX_HITS_ITERATIONS: u32 = 1000;
+
+fn default_listen() -> String {
+    "127.0.0.1:3000".to_owned()
+}
+
+fn default_workers() -> usize {
+    num_cpus::get()
+}
+
+fn default_log_level() -> String {
+    "info".to_owned()
+}
+
+fn default_max_results() -> usize {
+    150
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_fields() -> Vec<FieldConfig> {
+    vec![
+        FieldConfig::new("text", 1.0),
+        FieldConfig::new("headings", 5.0),
+        FieldConfig::new("title", 10.0),
+        FieldConfig::new("tags", 75.0),
+    ]
+}
+
+/// A manifest document field to index.
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct FieldConfig {
+    pub name: String,
+    pub weight: f32,
+
+    /// Stem this field's words. Unstemmed words are still found by prefix.
+    #[serde(default = "default_true")]
+    pub stemmed: bool,
+
+    /// Return this field's contents alongside search results.
+    #[serde(default)]
+    pub stored: bool,
+}
+
+impl FieldConfig {
+    fn new(name: &str, weight: f32) -> Self {
+        Self {
+            name: name.to_owned(),
+            weight,
+            stemmed: true,
+            stored: false,
+        }
+    }
+}
+
+/// How links between documents contribute to their authority.
+#[derive(Deserialize, Debug, PartialEq, Clone, Copy)]
+pub enum LinkAnalysis {
+    /// Run HITS over the links among each query's matches and their neighbors.
+    #[serde(rename = "hits")]
+    Hits,
+
+    /// Use each document's PageRank across the whole index, computed when it is built.
+    #[serde(rename = "pagerank")]
+    PageRank,
+
+    #[serde(rename = "none")]
+    None,
+}
+
+impl FromStr for LinkAnalysis {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hits" => Ok(LinkAnalysis::Hits),
+            "pagerank" => Ok(LinkAnalysis::PageRank),
+            "none" => Ok(LinkAnalysis::None),
+            _ => Err(format!("Unknown link analysis: {}", s)),
+        }
+    }
+}
+
+/// Which query terms also match the indexed terms they are a prefix of.
+#[derive(Deserialize, Debug, PartialEq, Clone, Copy)]
+pub enum PrefixExpansion {
+    #[serde(rename = "all")]
+    All,
+
+    /// Only expand the final term, if the user may still be typing it.
+    #[serde(rename = "last")]
+    Last,
+
+    #[serde(rename = "none")]
+    None,
+}
+
+impl FromStr for PrefixExpansion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(PrefixExpansion::All),
+            "last" => Ok(PrefixExpansion::Last),
+            "none" => Ok(PrefixExpansion::None),
+            _ => Err(format!("Unknown prefix expansion: {}", s)),
+        }
+    }
+}
+
+/// Scales the score of every document whose URL contains a pattern.
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct UrlBoost {
+    pub pattern: String,
+
+    /// Greater than 1 to promote matching documents; less than 1 to demote them.
+    pub boost: f32,
+}
+
+/// Constants used to rank search results.
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct RankingConfig {
+    /// The model used to score each matching term.
+    pub scorer: ScorerKind,
+
+    /// The dirichlet+ smoothing parameter.
+    pub mu: f32,
+
+    /// The dirichlet+ lower bound on each matching term's contribution.
+    pub delta: f32,
+
+    /// The BM25 term frequency saturation and length normalization parameters.
+    pub bm25_k1: f32,
+    pub bm25_b: f32,
+
+    /// Treat each field as at least this many tokens long when estimating
+    /// the probability of a term, so that tiny fields don't dominate.
+    pub min_field_tokens: u32,
+
+    pub link_analysis: LinkAnalysis,
+
+    /// Stop HITS once the hub and authority norms change by less than this.
+    pub hits_threshold: f32,
+    pub hits_iterations: u32,
+
+    /// The weight of the authority score relative to relevancy.
+    pub authority_weight: f32,
+
+    /// Penalize results whose relevancy is less than this multiple of the
+    /// relevancy threshold, which is the standard deviation of the relevancy
+    /// of every match.
+    pub penalty_threshold: f32,
+
+    /// How much to boost the relevancy of results whose query terms appear
+    /// close together, as a fraction of that relevancy. Off by default.
+    pub proximity_weight: f32,
+
+    /// Query terms matching fewer than this many documents also match indexed
+    /// terms within a few edits of them, with this weight.
+    pub fuzzy_threshold: u32,
+    pub fuzzy_weight: f32,
+
+    pub prefix_expansion: PrefixExpansion,
+
+    /// Only expand stemmed terms at least this many characters long, to at
+    /// most this many of the most common indexed terms, with this weight.
+    pub prefix_min_length: usize,
+    pub prefix_max_expansions: usize,
+    pub prefix_weight: f32,
+
+    /// Rules boosting or demoting documents by URL. A document matching
+    /// several rules has all of their boosts applied.
+    pub url_boosts: Vec<UrlBoost>,
+}
+
+impl Default for RankingConfig {
+    fn default() -> Self {
+        Self {
+            scorer: ScorerKind::DirichletPlus,
+
+            // In the range suggested by A Study of Smoothing Methods for Language Models
+            // Applied to Ad Hoc Information Retrieval [Zhai, Lafferty]
+            mu: 2000.0,
+            delta: 0.05,
+            bm25_k1: 1.2,
+            bm25_b: 0.75,
+            min_field_tokens: 500,
+            link_analysis: LinkAnalysis::Hits,
+            hits_threshold: 0.00001,
+            hits_iterations: 200,
+            authority_weight: 1.0 / 2.0, // 1.0 / log2(4)
+            penalty_threshold: 2.5,
+            proximity_weight: 0.0,
+            fuzzy_threshold: 3,
+            fuzzy_weight: 0.2,
+            prefix_expansion: PrefixExpansion::All,
+            prefix_min_length: 3,
+            prefix_max_expansions: 100,
+            prefix_weight: 0.1,
+            url_boosts: vec![],
+        }
+    }
+}
+
+fn parse_ranking_value<T: FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value for ranking parameter {}: {}", name, value))
+}
+
+impl RankingConfig {
+    /// The product of the boosts of every rule matching a URL.
+    pub fn url_boost(&self, url: &str) -> f32 {
+        self.url_boosts
+            .iter()
+            .filter(|rule| url.contains(&rule.pattern))
+            .fold(1.0, |boost, rule| boost * rule.boost)
+    }
+
+    /// Apply overrides of the form "mu:1500,delta:0.1".
+    pub fn apply_overrides(&mut self, overrides: &str) -> Result<(), String> {
+        for item in overrides.split(',').filter(|item| !item.is_empty()) {
+            let mut parts = item.splitn(2, ':');
+            let name = parts.next().unwrap_or("");
+            let value = parts
+                .next()
+                .ok_or_else(|| format!("Missing value for ranking parameter {}", name))?;
+
+            match name {
+                "scorer" => self.scorer = parse_ranking_value(name, value)?,
+                "mu" => self.mu = parse_ranking_value(name, value)?,
+                "delta" => self.delta = parse_ranking_value(name, value)?,
+                "bm25_k1" => self.bm25_k1 = parse_ranking_value(name, value)?,
+                "bm25_b" => self.bm25_b = parse_ranking_value(name, value)?,
+                "min_field_tokens" => self.min_field_tokens = parse_ranking_value(name, value)?,
+                "link_analysis" => self.link_analysis = parse_ranking_value(name, value)?,
+                "hits_threshold" => self.hits_threshold = parse_ranking_value(name, value)?,
+                "hits_iterations" => self.hits_iterations = parse_ranking_value(name, value)?,
+                "authority_weight" => self.authority_weight = parse_ranking_value(name, value)?,
+                "penalty_threshold" => self.penalty_threshold = parse_ranking_value(name, value)?,
+                "proximity_weight" => self.proximity_weight = parse_ranking_value(name, value)?,
+                "fuzzy_threshold" => self.fuzzy_threshold = parse_ranking_value(name, value)?,
+                "fuzzy_weight" => self.fuzzy_weight = parse_ranking_value(name, value)?,
+                "prefix_expansion" => self.prefix_expansion = parse_ranking_value(name, value)?,
+                "prefix_min_length" => self.prefix_min_length = parse_ranking_value(name, value)?,
+                "prefix_max_expansions" => {
+                    self.prefix_max_expansions = parse_ranking_value(name, value)?
+                }
+                "prefix_weight" => self.prefix_weight = parse_ranking_value(name, value)?,
+                _ => return Err(format!("Unknown ranking parameter: {}", name)),
+            }
+        }
+
+        self.validate()
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let non_negative = [
+            ("delta", self.delta),
+            ("bm25_k1", self.bm25_k1),
+            ("authority_weight", self.authority_weight),
+            ("penalty_threshold", self.penalty_threshold),
+            ("proximity_weight", self.proximity_weight),
+            ("fuzzy_weight", self.fuzzy_weight),
+            ("prefix_weight", self.prefix_weight),
+        ];
+
+        for &(name, value) in &non_negative {
+            if !value.is_finite() || value < 0.0 {
+                return Err(format!("Ranking parameter {} must be finite and not negative", name));
+            }
+        }
+
+        if !self.mu.is_finite() || self.mu <= 0.0 {
+            return Err(String::from("Ranking parameter mu must be finite and positive"));
+        }
+
+        if self.bm25_b.is_nan() || self.bm25_b < 0.0 || self.bm25_b > 1.0 {
+            return Err(String::from("Ranking parameter bm25_b must be between 0 and 1"));
+        }
+
+        if self.min_field_tokens == 0 {
+            return Err(String::from("Ranking parameter min_field_tokens must be positive"));
+        }
+
+        if !self.hits_threshold.is_finite() || self.hits_threshold <= 0.0 {
+            return Err(String::from(
+                "Ranking parameter hits_threshold must be finite and positive",
+            ));
+        }
+
+        if self.hits_iterations > MAX_HITS_ITERATIONS {
+            return Err(format!(
+                "Ranking parameter hits_iterations must be at most {}",
+                MAX_HITS_ITERATIONS
+            ));
+        }
+
+        for rule in &self.url_boosts {
+            if !rule.boost.is_finite() || rule.boost <= 0.0 {
+                return Err(format!(
+                    "Boost for URL pattern {} must be finite and positive",
+                    rule.pattern
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default = "default_listen")]
+    pub listen: String,
+
+    #[serde(default = "default_workers")]
+    pub workers: usize,
+
+    #[serde(default = "default_log_level")]
+    pub log_level: String,
+
+    #[serde(default)]
+    pub sources: Vec<String>,
+
+    #[serde(default)]
+    pub snapshot: Option<PathBuf>,
+
+    #[serde(default)]
+    pub pinned: Option<PathBuf>,
+
+    #[serde(default)]
+    pub refresh_interval: Option<u64>,
+
+    #[serde(default)]
+    pub watch: bool,
+
+    #[serde(default = "default_max_results")]
+    pub max_results: usize,
+
+    #[serde(default = "default_fields")]
+    pub fields: Vec<FieldConfig>,
+
+    #[serde(default)]
+    pub ranking: RankingConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            listen: default_listen(),
+            workers: default_workers(),
+            log_level: default_log_level(),
+            sources: vec![],
+            snapshot: None,
+            pinned: None,
+            refresh_interval: None,
+            watch: false,
+            max_results: default_max_results(),
+            fields: default_fields(),
+            ranking: RankingConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Parse a configuration file. Files ending in .json are parsed as JSON;
+    /// anything else is parsed as TOML.
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let mut file = File::open(path)
+            .map_err(|_| format!("Failed to open configuration file: {}", path.display()))?;
+        let mut data = String::new();
+        file.read_to_string(&mut data)
+            .map_err(|_| format!("Failed to read configuration file: {}", path.display()))?;
+
+        let is_json = path.extension().map(|ext| ext == "json").unwrap_or(false);
+        let parsed = if is_json {
+            serde_json::from_str(&data).map_err(|err| err.to_string())
+        } else {
+            toml::from_str(&data).map_err(|err| err.to_string())
+        };
+
+        parsed.map_err(|msg| {
+            format!(
+                "Failed to parse configuration file: {}\n{}",
+                path.display(),
+                msg
+            )
+        })
+    }
+
+    /// Create empty index fields as configured.
+    pub fn index_fields(&self) -> Vec<Field> {
+        self.fields.iter().map(Field::from_config).collect()
+    }
+
+    pub fn log_level(&self) -> Result<LevelFilter, String> {
+        LevelFilter::from_str(&self.log_level)
+            .map_err(|_| format!("Unknown log level: {}", self.log_level))
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        self.log_level()?;
+
+        if self.workers == 0 {
+            return Err(String::from("Must have at least one worker"));
+        }
+
+        if self.fields.is_empty() {
+            return Err(String::from("Must index at least one field"));
+        }
+
+        for (i, field) in self.fields.iter().enumerate() {
+            if !field.weight.is_finite() || field.weight < 0.0 {
+                return Err(format!("Invalid weight for field {}", field.name));
+            }
+
+            if self.fields[..i].iter().any(|f| f.name == field.name) {
+                return Err(format!("Field {} is declared more than once", field.name));
+            }
+        }
+
+        self.ranking.validate()?;
+
+        if self.max_results == 0 {
+            return Err(String::from("Must allow at least one result"));
+        }
+
+        if self.refresh_interval == Some(0) {
+            return Err(String::from("Refresh interval must be at least one second"));
+        }
+
+        if self.sources.is_empty() {
+            return Err(String::from("No manifest source given"));
+        }
+
+        // Only directories can be watched for changes
+        if self.watch && !self.sources.iter().any(|source| source.starts_with("dir:")) {
+            return Err(String::from("Watching for changes requires a dir: source"));
+        }
+
+        Ok(())
+    }
+}
+
+/// Settings for evaluating search quality against graded judgments.
+#[derive(Debug, PartialEq)]
+pub struct EvalOptions {
+    pub queries: PathBuf,
+    pub config: Config,
+    pub compare: Option<Config>,
+    pub k: usize,
+    pub search_properties: Vec<String>,
+}
+
+/// What the command line has asked us to do.
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Help,
+    Serve(Config),
+    Eval(EvalOptions),
+}
+
+/// Split "--flag=value" into its parts, or return the argument unchanged.
+fn split_flag(arg: &str) -> (&str, Option<&str>) {
+    if arg.starts_with("--") {
+        if let Some(i) = arg.find('=') {
+            return (&arg[..i], Some(&arg[i + 1..]));
+        }
+    }
+
+    (arg, None)
+}
+
+/// Parse the command line arguments (excluding the program name), loading
+/// and overlaying a configuration file if one is given.
+pub fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Command, String> {
+    let mut args = args.into_iter().peekable();
+    if args.peek().map(|arg| arg == "eval").unwrap_or(false) {
+        args.next();
+        return parse_eval_args(args);
+    }
+
+    let mut config_path: Option<PathBuf> = None;
+    let mut listen: Option<String> = None;
+    let mut workers: Option<usize> = None;
+    let mut log_level: Option<String> = None;
+    let mut snapshot: Option<PathBuf> = None;
+    let mut pinned: Option<PathBuf> = None;
+    let mut refresh_interval: Option<u64> = None;
+    let mut watch = false;
+    let mut max_results: Option<usize> = None;
+    let mut sources: Vec<String> = vec![];
+
+    while let Some(arg) = args.next() {
+        let (flag, inline_value) = split_flag(&arg);
+        if !flag.starts_with('-') {
+            sources.push(arg.to_owned());
+            continue;
+        }
+
+        if flag == "-h" || flag == "--help" {
+            return Ok(Command::Help);
+        }
+
+        if flag == "--watch" {
+            watch = true;
+            continue;
+        }
+
+        let value = match inline_value {
+            Some(v) => v.to_owned(),
+            None => args
+                .next()
+                .ok_or_else(|| format!("Missing value for {}", flag))?,
+        };
+
+        match flag {
+            "-c" | "--config" => config_path = Some(PathBuf::from(value)),
+            "-l" | "--listen" => listen = Some(value),
+            "-w" | "--workers" => {
+                workers = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("Invalid worker count: {}", value))?,
+                )
+            }
+            "--log-level" => log_level = Some(value),
+            "--snapshot" => snapshot = Some(PathBuf::from(value)),
+            "--pinned" => pinned = Some(PathBuf::from(value)),
+            "--refresh-interval" => {
+                refresh_interval = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("Invalid refresh interval: {}", value))?,
+                )
+            }
+            "--max-results" => {
+                max_results = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("Invalid result count: {}", value))?,
+                )
+            }
+            _ => return Err(format!("Unknown option: {}", flag)),
+        }
+    }
+
+    let mut config = match config_path {
+        Some(path) => Config::from_file(&path)?,
+        None => Config::default(),
+    };
+
+    if let Some(listen) = listen {
+        config.listen = listen;
+    }
+
+    if let Some(workers) = workers {
+        config.workers = workers;
+    }
+
+    if let Some(log_level) = log_level {
+        config.log_level = log_level;
+    }
+
+    if snapshot.is_some() {
+        config.snapshot = snapshot;
+    }
+
+    if pinned.is_some() {
+        config.pinned = pinned;
+    }
+
+    if refresh_interval.is_some() {
+        config.refresh_interval = refresh_interval;
+    }
+
+    if watch {
+        config.watch = true;
+    }
+
+    if let Some(max_results) = max_results {
+        config.max_results = max_results;
+    }
+
+    if !sources.is_empty() {
+        config.sources = sources;
+    }
+
+    config.validate()?;
+    Ok(Command::Serve(config))
+}
+
+fn parse_eval_args<I: Iterator<Item = String>>(mut args: I) -> Result<Command, String> {
+    let mut config_path: Option<PathBuf> = None;
+    let mut compare_path: Option<PathBuf> = None;
+    let mut k = 10;
+    let mut search_properties: Vec<String> = vec![];
+    let mut positional: Vec<String> = vec![];
+
+    while let Some(arg) = args.next() {
+        let (flag, inline_value) = split_flag(&arg);
+        if !flag.starts_with('-') {
+            positional.push(arg.to_owned());
+            continue;
+        }
+
+        if flag == "-h" || flag == "--help" {
+            return Ok(Command::Help);
+        }
+
+        let value = match inline_value {
+            Some(v) => v.to_owned(),
+            None => args
+                .next()
+                .ok_or_else(|| format!("Missing value for {}", flag))?,
+        };
+
+        match flag {
+            "-c" | "--config" => config_path = Some(PathBuf::from(value)),
+            "--compare" => compare_path = Some(PathBuf::from(value)),
+            "-k" => {
+                k = value
+                    .parse()
+                    .map_err(|_| format!("Invalid result count: {}", value))?
+            }
+            "--search-property" => search_properties.push(value),
+            _ => return Err(format!("Unknown option: {}", flag)),
+        }
+    }
+
+    if positional.is_empty() {
+        return Err(String::from("No queries file given"));
+    }
+
+    if k == 0 {
+        return Err(String::from("Must evaluate at least one result"));
+    }
+
+    let queries = PathBuf::from(positional.remove(0));
+
+    let mut config = match config_path {
+        Some(path) => Config::from_file(&path)?,
+        None => Config::default(),
+    };
+
+    if !positional.is_empty() {
+        config.sources = positional;
+    }
+
+    config.validate()?;
+
+    // The comparison shares our manifests unless it names its own
+    let compare = match compare_path {
+        Some(path) => {
+            let mut compare = Config::from_file(&path)?;
+            if compare.sources.is_empty() {
+                compare.sources = config.sources.to_owned();
+            }
+
+            compare.validate()?;
+            Some(compare)
+        }
+        None => None,
+    };
+
+    Ok(Command::Eval(EvalOptions {
+        queries,
+        config,
+        compare,
+        k,
+        search_properties,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(|s| s.to_owned()).collect()
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config = match parse_args(args("dir:test/manifests/")).unwrap() {
+            Command::Serve(config) => config,
+            _ => panic!("Expected to serve"),
+        };
+
+        assert_eq!(
+            config,
+            Config {
+                sources: vec!["dir:test/manifests/".to_owned()],
+                ..Config::default()
+            }
+        );
+        assert_eq!(config.log_level(), Ok(LevelFilter::Info));
+    }
+
+    #[test]
+    fn test_flags() {
+        let config = match parse_args(args(
+            "--listen 0.0.0.0:8080 -w 3 --log-level=debug --snapshot /tmp/index \
+             --pinned=pinned.toml --refresh-interval 60 --watch --max-results=20 dir:a bucket:b/c",
+        )).unwrap()
+        {
+            Command::Serve(config) => config,
+            _ => panic!("Expected to serve"),
+        };
+
+        assert_eq!(config.listen, "0.0.0.0:8080");
+        assert_eq!(config.workers, 3);
+        assert_eq!(config.log_level(), Ok(LevelFilter::Debug));
+        assert_eq!(config.snapshot, Some(PathBuf::from("/tmp/index")));
+        assert_eq!(config.pinned, Some(PathBuf::from("pinned.toml")));
+        assert_eq!(config.refresh_interval, Some(60));
+        assert!(config.watch);
+        assert_eq!(config.max_results, 20);
+        assert_eq!(
+            config.sources,
+            vec!["dir:a".to_owned(), "bucket:b/c".to_owned()]
+        );
+    }
+
+    #[test]
+    fn test_help() {
+        assert_eq!(parse_args(args("dir:a --help")), Ok(Command::Help));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(parse_args(args("")).is_err());
+        assert!(parse_args(args("dir:a --workers")).is_err());
+        assert!(parse_args(args("dir:a --workers many")).is_err());
+        assert!(parse_args(args("dir:a --log-level loud")).is_err());
+        assert!(parse_args(args("dir:a --refresh-interval 0")).is_err());
+        assert!(parse_args(args("dir:a --max-results 0")).is_err());
+        assert!(parse_args(args("dir:a --frobnicate 1")).is_err());
+        assert!(parse_args(args("bucket:b/c --watch")).is_err());
+    }
+
+    #[test]
+    fn test_eval() {
+        let options = match parse_args(args(
+            "eval -k 5 --search-property manual test/queries.json dir:a",
+        )).unwrap()
+        {
+            Command::Eval(options) => options,
+            _ => panic!("Expected to evaluate"),
+        };
+
+        assert_eq!(options.queries, PathBuf::from("test/queries.json"));
+        assert_eq!(options.config.sources, vec!["dir:a".to_owned()]);
+        assert_eq!(options.compare, None);
+        assert_eq!(options.k, 5);
+        assert_eq!(options.search_properties, vec!["manual".to_owned()]);
+
+        assert!(parse_args(args("eval")).is_err());
+        assert!(parse_args(args("eval test/queries.json")).is_err());
+        assert!(parse_args(args("eval -k 0 test/queries.json dir:a")).is_err());
+        assert_eq!(parse_args(args("eval --help")), Ok(Command::Help));
+    }
+
+    #[test]
+    fn test_parse_toml() {
+        let config: Config = toml::from_str(
+            r#"
+            listen = "0.0.0.0:3000"
+            workers = 2
+            sources = ["dir:a", "bucket:b/c"]
+            "#,
+        ).unwrap();
+
+        assert_eq!(config.listen, "0.0.0.0:3000");
+        assert_eq!(config.workers, 2);
+        assert_eq!(config.log_level, "info");
+        assert_eq!(config.sources.len(), 2);
+        assert_eq!(config.fields, default_fields());
+        assert_eq!(config.ranking, RankingConfig::default());
+        assert!(toml::from_str::<Config>("bogus = 1").is_err());
+    }
+
+    #[test]
+    fn test_parse_fields() {
+        let mut config: Config = toml::from_str(
+            r#"
+            sources = ["dir:a"]
+
+            [[fields]]
+            name = "title"
+            weight = 10.0
+
+            [[fields]]
+            name = "api_signatures"
+            weight = 2.5
+            stemmed = false
+            stored = true
+            "#,
+        ).unwrap();
+
+        assert_eq!(
+            config.fields,
+            vec![
+                FieldConfig::new("title", 10.0),
+                FieldConfig {
+                    name: "api_signatures".to_owned(),
+                    weight: 2.5,
+                    stemmed: false,
+                    stored: true,
+                },
+            ]
+        );
+        assert_eq!(config.validate(), Ok(()));
+
+        config.fields.push(FieldConfig::new("title", 1.0));
+        assert!(config.validate().is_err());
+
+        config.fields.pop();
+        config.fields[0].weight = f32::INFINITY;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_ranking() {
+        let config: Config = toml::from_str(
+            r#"
+            sources = ["dir:a"]
+
+            [ranking]
+            scorer = "bm25"
+            link_analysis = "pagerank"
+            mu = 1500
+            authority_weight = 0.0
+
+            [[ranking.url_boosts]]
+            pattern = "/tutorial/"
+            boost = 2.0
+
+            [[ranking.url_boosts]]
+            pattern = "/release-notes/"
+            boost = 0.5
+            "#,
+        ).unwrap();
+
+        assert_eq!(config.ranking.scorer, ScorerKind::BM25);
+        assert_eq!(config.ranking.link_analysis, LinkAnalysis::PageRank);
+        assert_eq!(config.ranking.mu, 1500.0);
+        assert_eq!(config.ranking.authority_weight, 0.0);
+        assert_eq!(config.ranking.hits_iterations, 200);
+        assert_eq!(config.ranking.url_boost("https://example.com/tutorial/"), 2.0);
+        assert_eq!(config.ranking.url_boost("https://example.com/release-notes/tutorial/"), 1.0);
+        assert_eq!(config.ranking.url_boost("https://example.com/reference/"), 1.0);
+        assert_eq!(config.validate(), Ok(()));
+        assert!(toml::from_str::<Config>("[ranking]\nbogus = 1").is_err());
+
+        let mut ranking = RankingConfig::default();
+        assert_eq!(ranking.apply_overrides("delta:0.1,hits_iterations:10,scorer:bm25"), Ok(()));
+        assert_eq!(ranking.scorer, ScorerKind::BM25);
+        assert_eq!(ranking.apply_overrides("link_analysis:none"), Ok(()));
+        assert_eq!(ranking.link_analysis, LinkAnalysis::None);
+        assert_eq!(ranking.delta, 0.1);
+        assert_eq!(ranking.hits_iterations, 10);
+        assert_eq!(ranking.mu, 2000.0);
+
+        assert!(ranking.clone().apply_overrides("mu:0").is_err());
+        assert!(ranking.clone().apply_overrides("mu").is_err());
+        assert!(ranking.clone().apply_overrides("mu:lots").is_err());
+        assert!(ranking.clone().apply_overrides("delta:-1").is_err());
+        assert!(ranking.clone().apply_overrides("proximity_weight:-0.5").is_err());
+        assert!(ranking.clone().apply_overrides("bogus:1").is_err());
+        assert!(ranking.clone().apply_overrides("bm25_b:2").is_err());
+        assert!(ranking.clone().apply_overrides("scorer:tfidf").is_err());
+        assert!(ranking.clone().apply_overrides("link_analysis:salsa").is_err());
+        assert!(ranking.clone().apply_overrides("prefix_expansion:some").is_err());
+        assert!(ranking.clone().apply_overrides("hits_threshold:0").is_err());
+        assert!(ranking.clone().apply_overrides("hits_iterations:4294967295").is_err());
+        for name in &["mu", "delta", "bm25_k1", "authority_weight", "proximity_weight"] {
+            assert!(ranking.clone().apply_overrides(&format!("{}:inf", name)).is_err());
+        }
+        assert!(ranking.clone().apply_overrides("hits_threshold:inf").is_err());
+        assert!(ranking.clone().apply_overrides("fuzzy_weight:NaN").is_err());
+
+        ranking.url_boosts = vec![UrlBoost {
+            pattern: "/release-notes/".to_owned(),
+            boost: 0.0,
+        }];
+        assert!(ranking.validate().is_err());
+    }
+}
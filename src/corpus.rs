@@ -0,0 +1,298 @@
+use config::RankingConfig;
+use fts::{FTSIndex, Field, TokenizedDocument};
+use manifest::{Manifest, ManifestError};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::SystemTime;
+
+fn is_valid_boost(boost: Option<f32>) -> bool {
+    match boost {
+        Some(boost) => boost > 0.0 && boost.is_finite(),
+        None => true,
+    }
+}
+
+struct TokenizedManifest {
+    last_modified: SystemTime,
+    include_in_global_search: bool,
+    aliases: Vec<String>,
+    documents: Vec<TokenizedDocument>,
+}
+
+/// The tokenized contents of every loaded manifest. This is kept between
+/// refreshes so that manifests which haven't changed need not be read or
+/// tokenized again.
+pub struct Corpus {
+    manifests: BTreeMap<String, TokenizedManifest>,
+    errors: HashMap<String, String>,
+    rebuilt: HashSet<String>,
+}
+
+impl Corpus {
+    pub fn new() -> Self {
+        Self {
+            manifests: BTreeMap::new(),
+            errors: HashMap::new(),
+            rebuilt: HashSet::new(),
+        }
+    }
+
+    /// The modification time of each manifest we hold.
+    pub fn last_modified(&self) -> HashMap<String, SystemTime> {
+        self.manifests
+            .iter()
+            .map(|(search_property, manifest)| {
+                (search_property.to_owned(), manifest.last_modified)
+            })
+            .collect()
+    }
+
+    /// Bring the corpus up to date with a fresh set of manifests from a loader.
+    /// Manifests without a body are unchanged and retained as-is; manifests that
+    /// are missing from the set are dropped.
+    pub fn update(&mut self, manifests: Vec<Result<Manifest, ManifestError>>, fields: &[Field]) {
+        let mut seen = HashSet::new();
+        self.errors.clear();
+        self.rebuilt.clear();
+
+        for manifest in manifests {
+            let manifest = match manifest {
+                Ok(manifest) => manifest,
+                Err(err) => {
+                    self.errors.insert(err.search_property, err.message);
+                    continue;
+                }
+            };
+
+            seen.insert(manifest.search_property.to_owned());
+
+            let mut body = match manifest.body {
+                Some(body) => body,
+                None => {
+                    if !self.manifests.contains_key(&manifest.search_property) {
+                        self.errors.insert(
+                            manifest.search_property,
+                            String::from("Manifest reported as unchanged, but was never loaded"),
+                        );
+                    }
+                    continue;
+                }
+            };
+
+            if !is_valid_boost(body.boost)
+                || !body.documents.iter().all(|doc| is_valid_boost(doc.boost))
+            {
+                self.errors.insert(
+                    manifest.search_property,
+                    String::from("Boosts must be positive numbers"),
+                );
+                continue;
+            }
+
+            while body.url.ends_with('/') {
+                body.url.pop();
+            }
+
+            let base_url = &body.url;
+            let manifest_boost = body.boost.unwrap_or(1.0);
+            let documents = body
+                .documents
+                .drain(..)
+                .map(|mut doc| {
+                    while doc.slug.ends_with('/') {
+                        doc.slug.pop();
+                    }
+                    doc.url = format!("{}/{}", base_url, doc.slug);
+                    doc.boost = Some(doc.boost.unwrap_or(1.0) * manifest_boost);
+                    TokenizedDocument::new(doc, fields)
+                })
+                .collect();
+
+            self.rebuilt.insert(manifest.search_property.to_owned());
+            self.manifests.insert(
+                manifest.search_property,
+                TokenizedManifest {
+                    last_modified: manifest.last_modified,
+                    include_in_global_search: body.include_in_global_search,
+                    aliases: body.aliases,
+                    documents,
+                },
+            );
+        }
+
+        self.manifests
+            .retain(|search_property, _| seen.contains(search_property));
+    }
+
+    /// Build a new index from the corpus.
+    pub fn build_index(&self, fields: Vec<Field>, ranking: RankingConfig) -> FTSIndex {
+        let mut index = FTSIndex::new(fields, ranking);
+
+        for (search_property, manifest) in &self.manifests {
+            for alias in &manifest.aliases {
+                index.alias_search_property(alias.to_owned(), search_property.to_owned());
+            }
+
+            for doc in &manifest.documents {
+                index.add_tokenized(
+                    doc,
+                    manifest.include_in_global_search,
+                    search_property.to_owned(),
+                );
+            }
+        }
+
+        index.manifest_errors = self.errors.clone();
+        index.last_modified = self.last_modified();
+        index.rebuilt = self.rebuilt.clone();
+        index.finish();
+
+        index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::UrlBoost;
+    use manifest::{ManifestData, ManifestDocument};
+    use fts::SearchOptions;
+    use query::Query;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn make_manifest(search_property: &str, mtime: u64, title: Option<&str>) -> Manifest {
+        Manifest {
+            body: title.map(|title| ManifestData {
+                include_in_global_search: true,
+                aliases: vec![],
+                boost: None,
+                documents: vec![ManifestDocument {
+                    slug: "index.html".to_owned(),
+                    title: title.to_owned(),
+                    tags: "".to_owned(),
+                    headings: vec![],
+                    links: vec![],
+                    extra: HashMap::new(),
+                    metadata: HashMap::new(),
+                    boost: None,
+                    text: "".to_owned(),
+                    preview: "".to_owned(),
+                    url: "".to_owned(),
+                }],
+                url: format!("https://example.com/{}/", search_property),
+            }),
+            last_modified: UNIX_EPOCH + Duration::from_secs(mtime),
+            search_property: search_property.to_owned(),
+        }
+    }
+
+    fn fields() -> Vec<Field> {
+        vec![Field::new("title", 1.0)]
+    }
+
+    fn search(index: &FTSIndex, query: &str) -> Vec<String> {
+        index
+            .search(&Query::new(query, &[], &[]), &SearchOptions::default())
+            .results
+            .iter()
+            .map(|result| result.document.url.to_owned())
+            .collect()
+    }
+
+    #[test]
+    fn test_update() {
+        let mut corpus = Corpus::new();
+        corpus.update(
+            vec![
+                Ok(make_manifest("fox", 1, Some("Red fox"))),
+                Ok(make_manifest("wolf", 1, Some("Grey wolf"))),
+            ],
+            &fields(),
+        );
+
+        let index = corpus.build_index(fields(), RankingConfig::default());
+        assert_eq!(index.rebuilt, hashset!["fox".to_owned(), "wolf".to_owned()]);
+        assert_eq!(search(&index, "wolf"), vec!["https://example.com/wolf/"]);
+
+        // Reuse the unchanged fox manifest, rebuild the changed wolf manifest,
+        // and pick up a new badger manifest.
+        corpus.update(
+            vec![
+                Ok(make_manifest("fox", 1, None)),
+                Ok(make_manifest("wolf", 2, Some("Dire wolf"))),
+                Ok(make_manifest("badger", 1, Some("Honey badger"))),
+            ],
+            &fields(),
+        );
+
+        let index = corpus.build_index(fields(), RankingConfig::default());
+        assert_eq!(
+            index.rebuilt,
+            hashset!["wolf".to_owned(), "badger".to_owned()]
+        );
+        assert_eq!(search(&index, "fox"), vec!["https://example.com/fox/"]);
+        assert_eq!(search(&index, "dire"), vec!["https://example.com/wolf/"]);
+        assert!(search(&index, "grey").is_empty());
+        assert_eq!(
+            index.last_modified["wolf"],
+            UNIX_EPOCH + Duration::from_secs(2)
+        );
+
+        // Drop manifests that disappear
+        corpus.update(
+            vec![
+                Ok(make_manifest("fox", 1, None)),
+                Err(ManifestError {
+                    search_property: "wolf".to_owned(),
+                    message: "Oops".to_owned(),
+                }),
+            ],
+            &fields(),
+        );
+
+        let index = corpus.build_index(fields(), RankingConfig::default());
+        assert!(index.rebuilt.is_empty());
+        assert!(search(&index, "wolf").is_empty());
+        assert!(search(&index, "badger").is_empty());
+        assert_eq!(index.manifest_errors, hashmap!["wolf".to_owned() => "Oops".to_owned()]);
+        assert_eq!(index.manifests, hashset!["fox".to_owned()]);
+    }
+
+    #[test]
+    fn test_boost() {
+        let mut fox = make_manifest("fox", 1, Some("Red fox"));
+        let mut wolf = make_manifest("wolf", 1, Some("Red wolf"));
+        fox.body.as_mut().unwrap().documents[0].boost = Some(2.0);
+        wolf.body.as_mut().unwrap().boost = Some(3.0);
+
+        let mut corpus = Corpus::new();
+        corpus.update(vec![Ok(fox), Ok(wolf)], &fields());
+        let index = corpus.build_index(fields(), RankingConfig::default());
+        assert_eq!(
+            search(&index, "red"),
+            vec!["https://example.com/wolf/", "https://example.com/fox/"]
+        );
+
+        // Configured rules apply on top of the manifests' own boosts
+        let mut ranking = RankingConfig::default();
+        ranking.url_boosts = vec![UrlBoost {
+            pattern: "/wolf/".to_owned(),
+            boost: 0.5,
+        }];
+        let index = corpus.build_index(fields(), ranking);
+        assert_eq!(
+            search(&index, "red"),
+            vec!["https://example.com/fox/", "https://example.com/wolf/"]
+        );
+
+        // Reject manifests with nonsensical boosts
+        let mut badger = make_manifest("badger", 2, Some("Red badger"));
+        badger.body.as_mut().unwrap().documents[0].boost = Some(-1.0);
+        corpus.update(vec![Ok(badger)], &fields());
+        let index = corpus.build_index(fields(), RankingConfig::default());
+        assert!(search(&index, "red").is_empty());
+        assert_eq!(
+            index.manifest_errors,
+            hashmap!["badger".to_owned() => "Boosts must be positive numbers".to_owned()]
+        );
+    }
+}
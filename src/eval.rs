@@ -0,0 +1,288 @@
+use config::{Config, EvalOptions};
+use corpus::Corpus;
+use fts::{FTSIndex, SearchOptions};
+use manifest::parse_manifest_sources;
+use query::Query;
+use serde_json;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+/// Each query's relevance judgments: a grade for each document slug, where
+/// any grade above zero is relevant.
+type Judgments = BTreeMap<String, HashMap<String, f64>>;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Metrics {
+    pub ndcg: f64,
+    pub average_precision: f64,
+    pub reciprocal_rank: f64,
+    pub precision: f64,
+}
+
+impl Metrics {
+    fn mean(metrics: &[Metrics]) -> Self {
+        let n = metrics.len().max(1) as f64;
+        Self {
+            ndcg: metrics.iter().map(|m| m.ndcg).sum::<f64>() / n,
+            average_precision: metrics.iter().map(|m| m.average_precision).sum::<f64>() / n,
+            reciprocal_rank: metrics.iter().map(|m| m.reciprocal_rank).sum::<f64>() / n,
+            precision: metrics.iter().map(|m| m.precision).sum::<f64>() / n,
+        }
+    }
+
+    fn values(&self) -> [f64; 4] {
+        [
+            self.ndcg,
+            self.average_precision,
+            self.reciprocal_rank,
+            self.precision,
+        ]
+    }
+}
+
+/// Slugs may or may not end in index.html or a slash; judgments omit both.
+fn normalize_slug(slug: &str) -> &str {
+    let slug = slug.trim_right_matches('/');
+    let slug = if slug.ends_with("index.html") {
+        &slug[..slug.len() - "index.html".len()]
+    } else {
+        slug
+    };
+
+    slug.trim_matches('/')
+}
+
+/// Score a ranked list of slugs against a query's graded judgments. NDCG and
+/// precision consider only the top `k` results; MAP and MRR consider all of them.
+pub fn score(ranked: &[&str], judgments: &HashMap<String, f64>, k: usize) -> Metrics {
+    let grade = |slug: &str| *judgments.get(slug).unwrap_or(&0.0);
+    let gain = |grade: f64| 2f64.powf(grade) - 1.0;
+    let discount = |rank: usize| (rank as f64 + 2.0).log2();
+
+    let dcg: f64 = ranked
+        .iter()
+        .take(k)
+        .enumerate()
+        .map(|(i, slug)| gain(grade(slug)) / discount(i))
+        .sum();
+
+    let mut ideal: Vec<f64> = judgments.values().cloned().collect();
+    ideal.sort_by(|a, b| b.partial_cmp(a).unwrap());
+    let ideal_dcg: f64 = ideal
+        .iter()
+        .take(k)
+        .enumerate()
+        .map(|(i, &grade)| gain(grade) / discount(i))
+        .sum();
+
+    let n_relevant = judgments.values().filter(|&&grade| grade > 0.0).count();
+    let mut hits = 0;
+    let mut sum_precision = 0.0;
+    let mut reciprocal_rank = 0.0;
+    for (i, slug) in ranked.iter().enumerate() {
+        if grade(slug) <= 0.0 {
+            continue;
+        }
+
+        hits += 1;
+        sum_precision += f64::from(hits) / (i + 1) as f64;
+        if hits == 1 {
+            reciprocal_rank = 1.0 / (i + 1) as f64;
+        }
+    }
+
+    let relevant_in_k = ranked.iter().take(k).filter(|slug| grade(slug) > 0.0).count();
+
+    Metrics {
+        ndcg: if ideal_dcg > 0.0 {
+            dcg / ideal_dcg
+        } else {
+            0.0
+        },
+        average_precision: if n_relevant > 0 {
+            sum_precision / n_relevant as f64
+        } else {
+            0.0
+        },
+        reciprocal_rank,
+        precision: relevant_in_k as f64 / k as f64,
+    }
+}
+
+fn load_judgments(path: &Path) -> Result<Judgments, String> {
+    let file =
+        File::open(path).map_err(|_| format!("Failed to open queries file: {}", path.display()))?;
+    let judgments: Judgments = serde_json::from_reader(BufReader::new(file))
+        .map_err(|err| format!("Failed to parse queries file: {}\n{}", path.display(), err))?;
+
+    Ok(judgments
+        .into_iter()
+        .map(|(query, grades)| {
+            let grades = grades
+                .into_iter()
+                .map(|(slug, grade)| (normalize_slug(&slug).to_owned(), grade))
+                .collect();
+            (query, grades)
+        })
+        .collect())
+}
+
+fn build_index(config: &Config) -> Result<FTSIndex, String> {
+    let loader = parse_manifest_sources(&config.sources)?;
+    let manifests = loader.load(&HashMap::new())?;
+
+    let mut corpus = Corpus::new();
+    corpus.update(manifests, &config.index_fields());
+    let index = corpus.build_index(config.index_fields(), config.ranking.clone());
+
+    for (search_property, message) in &index.manifest_errors {
+        warn!("Error loading {}: {}", search_property, message);
+    }
+
+    Ok(index)
+}
+
+/// Run every judged query against the index, returning metrics in query order.
+fn evaluate(index: &FTSIndex, judgments: &Judgments, options: &EvalOptions) -> Vec<Metrics> {
+    let search_properties: Vec<&str> = options
+        .search_properties
+        .iter()
+        .map(|s| s.as_str())
+        .collect();
+
+    judgments
+        .iter()
+        .map(|(query_string, grades)| {
+            let query = Query::new(query_string, &search_properties, &index.field_names());
+            let results = index.search(&query, &SearchOptions::default());
+
+            // The same slug may appear in several manifests; only count it once
+            let mut seen = HashSet::new();
+            let ranked: Vec<&str> = results
+                .results
+                .iter()
+                .map(|result| normalize_slug(&result.document.slug))
+                .filter(|slug| seen.insert(*slug))
+                .collect();
+
+            score(&ranked, grades, options.k)
+        })
+        .collect()
+}
+
+fn print_row(label: &str, metrics: &Metrics, baseline: Option<&Metrics>) {
+    let cells: Vec<String> = match baseline {
+        Some(baseline) => metrics
+            .values()
+            .iter()
+            .zip(baseline.values().iter())
+            .map(|(value, old)| format!("{:.4} ({:+.4})", value, value - old))
+            .collect(),
+        None => metrics
+            .values()
+            .iter()
+            .map(|value| format!("{:.4}", value))
+            .collect(),
+    };
+
+    let width = if baseline.is_some() { 17 } else { 8 };
+    println!(
+        "{:<32} {:>w$} {:>w$} {:>w$} {:>w$}",
+        label,
+        cells[0],
+        cells[1],
+        cells[2],
+        cells[3],
+        w = width
+    );
+}
+
+/// Evaluate the configured ranking against the judgments in `options.queries`,
+/// printing per-query and mean metrics. If a second configuration is given,
+/// print its metrics instead, with the change from the first alongside.
+pub fn run(options: &EvalOptions) -> Result<(), String> {
+    let judgments = load_judgments(&options.queries)?;
+
+    let baseline = evaluate(&build_index(&options.config)?, &judgments, options);
+    let (metrics, baseline) = match options.compare {
+        Some(ref compare) => {
+            let metrics = evaluate(&build_index(compare)?, &judgments, options);
+            (metrics, Some(baseline))
+        }
+        None => (baseline, None),
+    };
+
+    let width = if baseline.is_some() { 17 } else { 8 };
+    println!(
+        "{:<32} {:>w$} {:>w$} {:>w$} {:>w$}",
+        "query",
+        format!("NDCG@{}", options.k),
+        "AP",
+        "RR",
+        format!("P@{}", options.k),
+        w = width
+    );
+
+    for (i, query) in judgments.keys().enumerate() {
+        print_row(
+            query,
+            &metrics[i],
+            baseline.as_ref().map(|baseline| &baseline[i]),
+        );
+    }
+
+    print_row(
+        "mean",
+        &Metrics::mean(&metrics),
+        baseline.map(|baseline| Metrics::mean(&baseline)).as_ref(),
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-6, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn test_normalize_slug() {
+        assert_eq!(normalize_slug("index.html"), "");
+        assert_eq!(normalize_slug("reference/glossary/index.html"), "reference/glossary");
+        assert_eq!(normalize_slug("reference/glossary/"), "reference/glossary");
+        assert_eq!(normalize_slug("reference/glossary"), "reference/glossary");
+    }
+
+    #[test]
+    fn test_score() {
+        let judgments = hashmap![
+            "a".to_owned() => 1.0,
+            "b".to_owned() => 0.0,
+            "c".to_owned() => 1.0,
+        ];
+
+        // A perfect ranking
+        let metrics = score(&["a", "c", "b"], &judgments, 2);
+        assert_close(metrics.ndcg, 1.0);
+        assert_close(metrics.average_precision, 1.0);
+        assert_close(metrics.reciprocal_rank, 1.0);
+        assert_close(metrics.precision, 1.0);
+
+        // Relevant results in positions 2 and 4, with an unjudged result at 3
+        let metrics = score(&["b", "a", "x", "c"], &judgments, 2);
+        let ideal = 1.0 + 1.0 / 3f64.log2();
+        assert_close(metrics.ndcg, (1.0 / 3f64.log2()) / ideal);
+        assert_close(metrics.average_precision, (1.0 / 2.0 + 2.0 / 4.0) / 2.0);
+        assert_close(metrics.reciprocal_rank, 0.5);
+        assert_close(metrics.precision, 0.5);
+
+        // Nothing relevant
+        assert_eq!(score(&["b"], &judgments, 2).reciprocal_rank, 0.0);
+        assert_eq!(score(&[], &hashmap![], 2), Metrics::default());
+    }
+}
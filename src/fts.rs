@@ -1,16 +1,40 @@
 #![allow(unknown_lints, doc_markdown)]
 
+use config::{FieldConfig, LinkAnalysis, PrefixExpansion, RankingConfig};
 use manifest::ManifestDocument;
-use query::Query;
+use pinned::PinnedResults;
+use query::{Candidate, Query};
+use scorer::{FieldStats, TermStats};
+use snapshot;
+use snippet::Hit;
+use spelling::{is_correctable, max_edits, Speller};
 use std::borrow::Borrow;
-use std::collections::{HashMap, HashSet};
-use std::{cmp, iter, mem};
-use stemmer::{is_stop_word, stem, tokenize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::SystemTime;
+use std::{cmp, iter};
+use stemmer::{is_stop_word, stem, tokenize, tokenize_spans};
 use time;
 use trie::Trie;
 
-const MAX_MATCHES: usize = 150;
-const LOG_4_DIVISOR: f32 = 1.0 / 2.0; // 1.0 / log2(4)
+const PAGERANK_DAMPING: f32 = 0.85;
+const PAGERANK_THRESHOLD: f32 = 0.00001;
+const PAGERANK_ITERATIONS: u32 = 100;
+
+/// The most indexed terms that a single query term may fuzzily match.
+const MAX_FUZZY_MATCHES: usize = 5;
+
+/// The most indexed terms to compare against a query term when looking for
+/// fuzzy matches, so that the cost doesn't grow with the size of the index.
+const MAX_FUZZY_CANDIDATES: usize = 10000;
+
+/// Don't suggest anything until at least this many characters have been typed.
+const MIN_SUGGESTION_LENGTH: usize = 2;
+
+/// The most indexed terms to weigh when completing a word, so that short
+/// prefixes stay cheap in large indexes.
+const MAX_SUGGESTION_CANDIDATES: usize = 200;
 
 lazy_static! {
     static ref INITIAL_CORRELATIONS: Vec<(&'static str, &'static str, f32)> = vec![
@@ -30,7 +54,7 @@ lazy_static! {
     ];
 }
 
-#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct DocID(pub u32);
 
 impl DocID {
@@ -74,39 +98,34 @@ fn compute_relevancy_threshold(matches: &[&SearchMatch]) -> f32 {
     (1.0 / (matches.len() as f32 - 1.0) * sum).sqrt()
 }
 
-/// Yuanhua Lv and ChengXiang Zhai. 2011. Lower-bounding term frequency
-/// normalization. In Proceedings of the 20th ACM international
-/// conference on Information and knowledge management (CIKM '11), Bettina
-/// Berendt, Arjen de Vries, Wenfei Fan, Craig Macdonald, Iadh Ounis, and
-/// Ian Ruthven (Eds.). ACM, New York, NY, USA, 7-16. DOI: <https://doi.org/10.1145/2063576.2063584>
-fn dirichlet_plus(
-    term_frequency_in_query: f32,
-    term_frequency_in_doc: u32,
-    term_probability_in_language: f32,
-    doc_length: u32,
-    query_length: u32,
-) -> f32 {
-    let delta = 0.05;
+/// Order two scores, treating NaN as lower than any number, so that a bad
+/// score sinks rather than panicking a sort.
+fn cmp_scores(a: f32, b: f32) -> cmp::Ordering {
+    a.partial_cmp(&b).unwrap_or_else(|| b.is_nan().cmp(&a.is_nan()))
+}
 
-    // In the range suggested by A Study of Smoothing Methods for Language Models
-    // Applied to Ad Hoc Information Retrieval [Zhai, Lafferty]
-    let mu = 2000.0;
+/// Return the smallest distance between two distinct positions from the
+/// sorted lists `a` and `b`.
+fn min_distance(a: &[TokenID], b: &[TokenID]) -> Option<u32> {
+    let (mut i, mut j) = (0, 0);
+    let mut min = None;
+    while i < a.len() && j < b.len() {
+        let distance = cmp::max(a[i], b[j]) - cmp::min(a[i], b[j]);
+        if distance > 0 {
+            min = Some(min.map_or(distance, |min| cmp::min(min, distance)));
+        }
 
-    // In some fields, the query may never exist, making its probability 0.
-    // This is... weird. Return 0 to avoid NaN since while dirichlet+
-    // prefers rare words, a nonexistent word should probably be ignored.
-    if term_probability_in_language == 0.0 {
-        return 0.0;
+        if a[i] < b[j] {
+            i += 1;
+        } else {
+            j += 1;
+        }
     }
 
-    let term2 = (1.0 + (term_frequency_in_doc as f32 / (mu * term_probability_in_language))).log2();
-    let term2 = term2 + (1.0 + (delta / (mu * term_probability_in_language))).log2();
-
-    let term3 = query_length as f32 * (mu / (doc_length as f32 + mu)).log2();
-
-    (term_frequency_in_query * term2) + term3
+    min
 }
 
+#[derive(Serialize, Deserialize)]
 struct TermEntry {
     docs: Vec<DocID>,
     positions: HashMap<DocID, Vec<TokenID>>,
@@ -127,6 +146,21 @@ impl TermEntry {
         *self.times_appeared.entry(field_name).or_insert(0) += 1;
     }
 
+    /// Return the number of distinct documents containing this term.
+    fn document_frequency(&self) -> u32 {
+        // Documents are registered in order, once for each field containing the term
+        let mut n = 0;
+        let mut last = None;
+        for &docid in &self.docs {
+            if last != Some(docid) {
+                n += 1;
+                last = Some(docid);
+            }
+        }
+
+        n
+    }
+
     fn add_token_position(&mut self, docid: DocID, token_id: TokenID) {
         self.positions
             .entry(docid)
@@ -135,18 +169,128 @@ impl TermEntry {
     }
 }
 
+#[derive(Serialize, Deserialize)]
 struct DocumentEntry {
     len: u32,
+
+    /// The position of the field's first token.
+    start: TokenID,
     term_frequencies: HashMap<String, u32>,
 }
 
 impl DocumentEntry {
-    fn new(number_of_tokens: u32, term_frequencies: HashMap<String, u32>) -> Self {
+    fn new(number_of_tokens: u32, start: TokenID, term_frequencies: HashMap<String, u32>) -> Self {
         DocumentEntry {
             len: number_of_tokens,
+            start,
             term_frequencies,
         }
     }
+
+    fn contains(&self, position: TokenID) -> bool {
+        position >= self.start && position < self.start + self.len
+    }
+}
+
+/// A document being checked against a query's required and excluded clauses.
+struct CandidateDocument<'a> {
+    index: &'a FTSIndex,
+    doc_id: DocID,
+
+    /// The indexed terms that each query term was expanded to when scoring,
+    /// in each document.
+    expansions: &'a HashMap<&'a str, HashMap<DocID, Vec<&'a str>>>,
+}
+
+impl<'a> CandidateDocument<'a> {
+    fn field_entry(&self, field: &str) -> Option<&DocumentEntry> {
+        self.index
+            .fields
+            .iter()
+            .find(|f| f.name == field)
+            .and_then(|f| f.documents.get(&self.doc_id))
+    }
+}
+
+impl<'a> Candidate for CandidateDocument<'a> {
+    fn has_term(&self, term: &str, field: Option<&str>, expand: bool) -> bool {
+        let expanded = match self.expansions.get(term) {
+            Some(docs) if expand => docs.get(&self.doc_id),
+            _ => None,
+        };
+
+        let mut terms = iter::once(term).chain(expanded.into_iter().flatten().cloned());
+        match field {
+            Some(field) => match self.field_entry(field) {
+                Some(entry) => terms.any(|t| entry.term_frequencies.contains_key(t)),
+                None => false,
+            },
+            None => terms.any(|t| match self.index.terms.get(t) {
+                Some(term_entry) => term_entry.positions.contains_key(&self.doc_id),
+                None => false,
+            }),
+        }
+    }
+
+    fn positions(&self, term: &str, field: Option<&str>) -> Option<Vec<u32>> {
+        let positions = self
+            .index
+            .terms
+            .get(term)
+            .and_then(|term_entry| term_entry.positions.get(&self.doc_id))?;
+
+        let positions: Vec<u32> = match field {
+            Some(field) => {
+                let entry = self.field_entry(field)?;
+                positions.iter().cloned().filter(|&p| entry.contains(p)).collect()
+            }
+            None => positions.to_owned(),
+        };
+
+        if positions.is_empty() {
+            None
+        } else {
+            Some(positions)
+        }
+    }
+
+    fn fields(&self) -> Vec<&str> {
+        self.index
+            .fields
+            .iter()
+            .filter(|f| f.documents.contains_key(&self.doc_id))
+            .map(|f| f.name.as_str())
+            .collect()
+    }
+}
+
+/// How much a single matched term contributed to a document's relevancy.
+#[derive(Serialize, Debug)]
+pub struct TermExplanation {
+    pub term: String,
+
+    /// 1.0 for terms from the query; less for correlated and prefix-matched terms.
+    pub weight: f32,
+
+    /// The contribution from each field, after field weighting.
+    pub fields: BTreeMap<String, f32>,
+}
+
+/// A breakdown of how a result's final score was computed.
+#[derive(Serialize, Debug)]
+pub struct Explanation {
+    pub terms: Vec<TermExplanation>,
+    pub relevancy: f32,
+    pub authority: f32,
+    pub hub: f32,
+    pub penalty: f32,
+
+    /// The amount added to relevancy because query terms appear close together.
+    pub proximity: f32,
+
+    /// The document's static boost, which scales its score.
+    pub boost: f32,
+    pub score: f32,
 }
 
 #[derive(Debug)]
@@ -154,10 +298,16 @@ struct SearchMatch {
     _id: DocID,
     relevancy_score: f32,
     terms: HashSet<String>,
+    term_explanations: Vec<TermExplanation>,
+    proximity: f32,
+    boost: f32,
 
     score: f32,
-    incoming_neighbors: HashSet<DocID>,
-    outgoing_neighbors: HashSet<DocID>,
+    authority_score: f32,
+    hub_score: f32,
+    penalty: f32,
+    incoming_neighbors: BTreeSet<DocID>,
+    outgoing_neighbors: BTreeSet<DocID>,
 }
 
 impl SearchMatch {
@@ -166,10 +316,29 @@ impl SearchMatch {
             _id: docid,
             relevancy_score: 0.0,
             terms: HashSet::new(),
+            term_explanations: vec![],
+            proximity: 0.0,
+            boost: 1.0,
 
             score: 0.0,
-            incoming_neighbors: hashset![],
-            outgoing_neighbors: hashset![],
+            authority_score: 0.0,
+            hub_score: 0.0,
+            penalty: 0.0,
+            incoming_neighbors: BTreeSet::new(),
+            outgoing_neighbors: BTreeSet::new(),
+        }
+    }
+
+    fn explain(&mut self) -> Explanation {
+        Explanation {
+            terms: self.term_explanations.drain(..).collect(),
+            relevancy: self.relevancy_score,
+            authority: self.authority_score,
+            hub: self.hub_score,
+            penalty: self.penalty,
+            proximity: self.proximity,
+            boost: self.boost,
+            score: self.score,
         }
     }
 
@@ -178,18 +347,27 @@ impl SearchMatch {
         max_relevancy_score: f32,
         authority_score: f32,
         max_authority_score: f32,
+        authority_weight: f32,
     ) {
         let normalized_relevancy_score = self.relevancy_score / max_relevancy_score + 1.0;
-        let normalized_authority_score = authority_score / max_authority_score + 1.0;
-        self.score =
-            normalized_relevancy_score.log2() + (normalized_authority_score.log2() * LOG_4_DIVISOR);
+        let normalized_authority_score = if max_authority_score > 0.0 {
+            authority_score / max_authority_score + 1.0
+        } else {
+            1.0
+        };
+        self.score = (normalized_relevancy_score.log2()
+            + (normalized_authority_score.log2() * authority_weight))
+            * self.boost;
     }
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct Field {
     name: String,
     documents: HashMap<DocID, DocumentEntry>,
     weight: f32,
+    stemmed: bool,
+    stored: bool,
     total_tokens: u32,
 
     length_weight: f32,
@@ -197,15 +375,34 @@ pub struct Field {
 
 impl Field {
     pub fn new(name: &str, weight: f32) -> Self {
-        Self {
+        Self::from_config(&FieldConfig {
             name: name.to_owned(),
-            documents: HashMap::new(),
             weight,
+            stemmed: true,
+            stored: false,
+        })
+    }
+
+    pub fn from_config(config: &FieldConfig) -> Self {
+        Self {
+            name: config.name.to_owned(),
+            documents: HashMap::new(),
+            weight: config.weight,
+            stemmed: config.stemmed,
+            stored: config.stored,
             total_tokens: 0,
             length_weight: 0.0,
         }
     }
 
+    /// Return true if the two fields would index documents identically.
+    fn has_same_definition(&self, other: &Field) -> bool {
+        self.name == other.name
+            && self.weight == other.weight
+            && self.stemmed == other.stemmed
+            && self.stored == other.stored
+    }
+
     /// Return the inverse average number of unique terms per document.
     /// This makes no fscking sense, but is useful as a weighting factor
     /// in my testing.
@@ -219,17 +416,252 @@ impl Field {
     }
 }
 
+/// A document matching a query, along with the indexed terms that it matched.
+pub struct SearchResult<'a> {
+    pub document: &'a Document,
+    pub terms: HashSet<String>,
+    pub explanation: Option<Explanation>,
+
+    /// Whether the document was pinned to the top of the results for this query.
+    pub pinned: bool,
+}
+
+/// Restricts a search to documents with particular metadata values. A document
+/// must have one of the allowed values for every filtered attribute.
+#[derive(Default, Debug, PartialEq)]
+pub struct Filter {
+    constraints: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl Filter {
+    fn matches(&self, doc: &Document) -> bool {
+        self.constraints.iter().all(|(key, allowed)| match doc.metadata.get(key) {
+            Some(values) => !values.is_disjoint(allowed),
+            None => false,
+        })
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    /// Parse a comma-separated list of key:value pairs.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut filter = Self::default();
+        for constraint in s.split(',') {
+            let mut parts = constraint.splitn(2, ':');
+            let key = parts.next().unwrap_or("").trim();
+            let value = match parts.next() {
+                Some(value) if !key.is_empty() => value.trim(),
+                _ => return Err(format!("Invalid filter: {}", constraint)),
+            };
+
+            filter
+                .constraints
+                .entry(key.to_owned())
+                .or_insert_with(BTreeSet::new)
+                .insert(value.to_lowercase());
+        }
+
+        Ok(filter)
+    }
+}
+
+/// Options controlling which results a search returns, and in how much detail.
+pub struct SearchOptions {
+    pub offset: usize,
+    pub limit: usize,
+
+    /// Explain how each result's score was computed.
+    pub explain: bool,
+
+    /// Count the matches in each search property.
+    pub facets: bool,
+
+    /// Only return documents whose metadata passes this filter.
+    pub filter: Filter,
+
+    /// Rank with these parameters instead of the index's own.
+    pub ranking: Option<RankingConfig>,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self {
+            offset: 0,
+            limit: usize::max_value(),
+            explain: false,
+            facets: false,
+            filter: Filter::default(),
+            ranking: None,
+        }
+    }
+}
+
+/// One page of ranked search results.
+pub struct SearchResults<'a> {
+    pub results: Vec<SearchResult<'a>>,
+
+    /// The total number of results, across all pages, including pinned
+    /// results.
+    pub total: usize,
+
+    /// Query terms with few matches, and the words behind the similar indexed
+    /// terms that were searched for as well.
+    pub fuzzy_matches: BTreeMap<String, Vec<String>>,
+
+    /// The number of results, across all pages, in each search property. Only
+    /// counted if requested.
+    pub facets: BTreeMap<&'a str, usize>,
+}
+
+/// Completions of a partially typed query.
+pub struct Suggestions<'a> {
+    /// The query with its final word completed, best first.
+    pub completions: Vec<String>,
+
+    /// Documents with a word in their title beginning with the query.
+    pub titles: Vec<&'a Document>,
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct Document {
     pub _id: DocID,
     pub url: String,
+    pub slug: String,
 
     pub title: String,
     pub preview: String,
 
+    /// Shared with the tokenized document, so that rebuilding doesn't copy it.
+    pub text: Arc<str>,
+
+    /// The contents of each stored field.
+    pub stored: BTreeMap<String, String>,
+
+    /// The lowercased values of each metadata attribute.
+    pub metadata: BTreeMap<String, BTreeSet<String>>,
+
+    /// Scales the document's score, before any configured URL boosts.
+    pub boost: f32,
+
     pub include_in_global_search: bool,
     pub search_property: String,
 }
 
+impl Document {
+    /// Return the contents of a field, if they are kept: the title and text
+    /// always are, and other fields only if stored.
+    pub fn field(&self, name: &str) -> Option<&str> {
+        match name {
+            "title" => Some(&self.title),
+            "text" => Some(&self.text),
+            _ => self.stored.get(name).map(|text| text.as_str()),
+        }
+    }
+}
+
+/// A document that has been tokenized and stemmed, ready to be added to an
+/// index. Tokenizing is the most expensive part of indexing, so these are kept
+/// around to rebuild the index cheaply when only some manifests change.
+pub struct TokenizedDocument {
+    url: String,
+    slug: String,
+    title: String,
+    preview: String,
+    text: Arc<str>,
+    links: Vec<String>,
+    stored: BTreeMap<String, String>,
+    metadata: BTreeMap<String, BTreeSet<String>>,
+    boost: f32,
+
+    fields: Vec<(String, Vec<String>)>,
+    words: HashMap<String, u32>,
+
+    /// The unstemmed words behind each stemmed token, and how often each appears.
+    surface_forms: HashMap<String, HashMap<String, u32>>,
+    correlations: Vec<(String, u8, f32)>,
+}
+
+impl TokenizedDocument {
+    pub fn new(mut document: ManifestDocument, fields: &[Field]) -> Self {
+        normalize_url(&mut document.url);
+        for href in &mut document.links {
+            normalize_url(href);
+        }
+
+        let mut tokenized_fields = vec![];
+        let mut stored = BTreeMap::new();
+        let mut words = HashMap::new();
+        let mut surface_forms: HashMap<String, HashMap<String, u32>> = HashMap::new();
+        let mut correlations: Vec<(String, u8, f32)> = vec![];
+
+        for field in fields {
+            let text = match document.get(&field.name) {
+                Some(t) => t,
+                None => continue,
+            };
+
+            if text.len() == 0 {
+                continue;
+            }
+
+            if field.stored {
+                stored.insert(field.name.to_owned(), text.to_string());
+            }
+
+            let mut tokens = vec![];
+            for token in tokenize(text.borrow(), true) {
+                if is_stop_word(&token) {
+                    continue;
+                }
+
+                if is_correctable(&token) {
+                    *words.entry(token.to_owned()).or_insert(0) += 1;
+                }
+
+                if token.starts_with("%%") {
+                    correlations.push((token.to_owned(), 2, 0.9));
+                    tokens.push(token);
+                } else if token.starts_with('$') || token.starts_with('%') {
+                    correlations.push((token.to_owned(), 1, 0.9));
+                    tokens.push(token);
+                } else if field.stemmed {
+                    let stemmed = stem(&token);
+                    *surface_forms
+                        .entry(stemmed.to_owned())
+                        .or_insert_with(HashMap::new)
+                        .entry(token)
+                        .or_insert(0) += 1;
+                    tokens.push(stemmed);
+                } else {
+                    tokens.push(token);
+                }
+            }
+
+            tokenized_fields.push((field.name.to_owned(), tokens));
+        }
+
+        let metadata = document.keywords();
+        Self {
+            url: document.url,
+            slug: document.slug,
+            title: document.title,
+            preview: document.preview,
+            text: Arc::from(document.text),
+            links: document.links,
+            stored,
+            metadata,
+            boost: document.boost.unwrap_or(1.0),
+
+            fields: tokenized_fields,
+            words,
+            surface_forms,
+            correlations,
+        }
+    }
+}
+
 struct MatchSet {
     matches: HashMap<DocID, SearchMatch>,
 }
@@ -290,11 +722,15 @@ impl MatchSet {
         }
     }
 
-    fn hits(&mut self, convergance_threshold: f32, max_iterations: u32) -> Vec<DocID> {
+    /// Compute the authority and hub score of each match within the base set.
+    fn hits(&self, ranking: &RankingConfig) -> (HashMap<DocID, f32>, HashMap<DocID, f32>) {
         let mut last_authority_norm = 0.0;
         let mut last_hub_norm = 0.0;
 
-        let match_ids = self.matches.keys().cloned().collect::<Vec<_>>();
+        // Visit matches in a fixed order so that floating point error, and thus
+        // the ranking, is the same from one search to the next.
+        let mut match_ids = self.matches.keys().cloned().collect::<Vec<_>>();
+        match_ids.sort();
         let mut authority_scores: HashMap<DocID, f32> = hashmap![];
         let mut hub_scores: HashMap<DocID, f32> = hashmap![];
         for &id in &match_ids {
@@ -302,7 +738,7 @@ impl MatchSet {
             hub_scores.insert(id, 1.0);
         }
 
-        for _ in 0..max_iterations {
+        for _ in 0..ranking.hits_iterations {
             let mut authority_norm = 0.0;
             // Update all authority scores
             for id in &match_ids {
@@ -343,8 +779,8 @@ impl MatchSet {
                 }
             }
 
-            if (authority_norm - last_authority_norm).abs() < convergance_threshold
-                && (hub_norm - last_hub_norm).abs() < convergance_threshold
+            if (authority_norm - last_authority_norm).abs() < ranking.hits_threshold
+                && (hub_norm - last_hub_norm).abs() < ranking.hits_threshold
             {
                 break;
             }
@@ -353,17 +789,25 @@ impl MatchSet {
             last_hub_norm = hub_norm;
         }
 
+        (authority_scores, hub_scores)
+    }
+
+    /// Combine each match's relevancy with its authority into a final score,
+    /// returning matches with any relevancy from best to worst.
+    fn rank(
+        &mut self,
+        mut authority_scores: HashMap<DocID, f32>,
+        hub_scores: &HashMap<DocID, f32>,
+        ranking: &RankingConfig,
+    ) -> Vec<DocID> {
+        let mut match_ids = self.matches.keys().cloned().collect::<Vec<_>>();
+        match_ids.sort();
+
         // Cut anything with zero relevancy
-        let mut matches: Vec<DocID> = self
-            .matches
-            .values()
-            .filter_map(|m| {
-                if m.relevancy_score > 0.0 {
-                    Some(m._id)
-                } else {
-                    None
-                }
-            })
+        let mut matches: Vec<DocID> = match_ids
+            .iter()
+            .cloned()
+            .filter(|id| self.matches[id].relevancy_score > 0.0)
             .collect();
 
         // Compute statistics for score normalization
@@ -375,7 +819,7 @@ impl MatchSet {
             .collect::<Vec<_>>());
         for id in &matches {
             let search_match = &self.matches[id];
-            let mut authority_score = authority_scores.get_mut(&search_match._id).unwrap();
+            let authority_score = authority_scores.entry(search_match._id).or_insert(0.0);
             if authority_score.is_nan() {
                 *authority_score = 1e-10;
             }
@@ -398,37 +842,40 @@ impl MatchSet {
         for id in &matches {
             let mut search_match = self.matches.get_mut(&id).unwrap();
             let authority_score = authority_scores[&search_match._id];
-            search_match.compute_score(max_relevancy_score, authority_score, max_authority_score);
+            search_match.compute_score(
+                max_relevancy_score,
+                authority_score,
+                max_authority_score,
+                ranking.authority_weight,
+            );
+            search_match.authority_score = authority_score;
+            search_match.hub_score = *hub_scores.get(&search_match._id).unwrap_or(&0.0);
 
             // Penalize anything with especially poor relevancy
-            if search_match.relevancy_score < relevancy_score_threshold * 2.5 {
-                search_match.score -= relevancy_score_threshold / search_match.relevancy_score;
+            if search_match.relevancy_score < relevancy_score_threshold * ranking.penalty_threshold {
+                search_match.penalty = relevancy_score_threshold / search_match.relevancy_score;
+                search_match.score -= search_match.penalty;
             }
         }
 
+        // Break ties by DocID so that paging through results is stable
         matches.sort_unstable_by(|a, b| {
-            self.matches[b]
-                .score
-                .partial_cmp(&self.matches[a].score)
-                .unwrap()
+            cmp_scores(self.matches[b].score, self.matches[a].score).then_with(|| a.cmp(b))
         });
-        matches.truncate(MAX_MATCHES);
-
-        // let id = matches[101];
-        // let search_match = self.matches.get_mut(&id).unwrap();
-        // println!("{} {} {}", id.usize(), search_match.relevancy_score, authority_scores.get(&id).unwrap());
-        // for id in &matches[..10] {
-        //     let search_match = self.matches.get_mut(&id).unwrap();
-        //     println!("{} {} {}", id.usize(), search_match.relevancy_score, authority_scores.get(id).unwrap());
-        // }
 
         matches
     }
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct FTSIndex {
     fields: Vec<Field>,
+    #[serde(skip)]
     trie: Trie,
+    /// Each document under every suffix of its lowercased title that begins
+    /// a word, for title suggestions.
+    #[serde(skip)]
+    titles: Trie,
     terms: HashMap<String, TermEntry>,
     doc_id: DocID,
     term_id: u32,
@@ -442,19 +889,38 @@ pub struct FTSIndex {
     incoming_neighbors: HashMap<DocID, Vec<DocID>>,
     outgoing_neighbors: HashMap<DocID, Vec<DocID>>,
 
+    /// Each document's PageRank, indexed by DocID.
+    pagerank: Vec<f32>,
+
     word_correlations: HashMap<String, Vec<(String, f32)>>,
     search_property_aliases: HashMap<String, String>,
+    speller: Speller,
+
+    /// The unstemmed words behind each stemmed token in each search property,
+    /// for suggestions.
+    surface_forms: HashMap<String, HashMap<String, HashMap<String, u32>>>,
 
+    #[serde(with = "snapshot::timespec")]
     pub finished: time::Timespec,
     pub manifests: HashSet<String>,
     pub manifest_errors: HashMap<String, String>,
+    pub last_modified: HashMap<String, SystemTime>,
+    pub rebuilt: HashSet<String>,
+
+    /// Ranking only applies at search time, so it is not saved in snapshots.
+    #[serde(skip)]
+    pub ranking: RankingConfig,
+
+    #[serde(skip)]
+    pub pinned: PinnedResults,
 }
 
 impl FTSIndex {
-    pub fn new(fields: Vec<Field>) -> Self {
+    pub fn new(fields: Vec<Field>, ranking: RankingConfig) -> Self {
         let mut index = Self {
             fields,
             trie: Trie::new(),
+            titles: Trie::new(),
             terms: HashMap::new(),
             doc_id: DocID(0),
             term_id: 0,
@@ -467,13 +933,20 @@ impl FTSIndex {
 
             incoming_neighbors: hashmap![],
             outgoing_neighbors: hashmap![],
+            pagerank: vec![],
 
             word_correlations: HashMap::new(),
             search_property_aliases: HashMap::new(),
+            speller: Speller::new(),
+            surface_forms: HashMap::new(),
 
             finished: time::Timespec::new(0, 0),
             manifests: HashSet::new(),
             manifest_errors: hashmap![],
+            last_modified: hashmap![],
+            rebuilt: HashSet::new(),
+            ranking,
+            pinned: PinnedResults::default(),
         };
 
         for (phrase, correlation, strength) in INITIAL_CORRELATIONS.iter() {
@@ -533,73 +1006,74 @@ impl FTSIndex {
 
     pub fn add(
         &mut self,
-        mut document: ManifestDocument,
+        document: ManifestDocument,
+        include_in_global_search: bool,
+        search_property: String,
+    ) {
+        let document = TokenizedDocument::new(document, &self.fields);
+        self.add_tokenized(&document, include_in_global_search, search_property);
+    }
+
+    pub fn add_tokenized(
+        &mut self,
+        document: &TokenizedDocument,
         include_in_global_search: bool,
         search_property: String,
     ) {
         let doc_id = self.doc_id;
         self.doc_id = self.doc_id.inc();
-        normalize_url(&mut document.url);
 
-        for mut href in &mut document.links {
-            normalize_url(&mut href);
-            let mut incoming_links = self
+        for href in &document.links {
+            let incoming_links = self
                 .inverse_link_graph
                 .entry(href.to_owned())
                 .or_insert_with(Vec::new);
             incoming_links.push(document.url.to_owned());
         }
 
-        self.link_graph.insert(
-            document.url.to_owned(),
-            mem::replace(&mut document.links, vec![]),
-        );
+        self.link_graph
+            .insert(document.url.to_owned(), document.links.to_owned());
         self.url_to_id.insert(document.url.to_owned(), doc_id);
         self.id_to_url.insert(doc_id, document.url.to_owned());
 
-        let mut correlations: Vec<(String, u8, f32)> = vec![];
+        for (word, &count) in &document.words {
+            self.speller.insert(word, count);
+        }
 
-        for field in &mut self.fields {
-            let mut term_frequencies = HashMap::new();
+        let property_forms = self
+            .surface_forms
+            .entry(search_property.to_owned())
+            .or_insert_with(HashMap::new);
+        for (token, forms) in &document.surface_forms {
+            let entry = property_forms
+                .entry(token.to_owned())
+                .or_insert_with(HashMap::new);
+            for (form, &count) in forms {
+                *entry.entry(form.to_owned()).or_insert(0) += count;
+            }
+        }
 
-            let text = match document.get(&field.name) {
-                Some(t) => t,
+        for &(ref field_name, ref tokens) in &document.fields {
+            let field = match self.fields.iter_mut().find(|f| &f.name == field_name) {
+                Some(f) => f,
                 None => continue,
             };
 
-            if text.len() == 0 {
-                continue;
-            }
-
-            let tokens = tokenize(text.borrow(), true);
-            let mut number_of_tokens = 0;
-
-            for token in &tokens {
-                if is_stop_word(token) {
-                    continue;
-                }
-
-                let mut token = token.to_owned();
-                if token.starts_with("%%") {
-                    correlations.push((token.to_owned(), 2, 0.9));
-                } else if token.starts_with('$') || token.starts_with('%') {
-                    correlations.push((token.to_owned(), 1, 0.9));
-                } else {
-                    token = stem(&token);
-                }
+            let mut term_frequencies = HashMap::new();
+            let start = self.term_id + 1;
 
-                number_of_tokens += 1;
+            for token in tokens {
                 self.term_id += 1;
 
-                let mut index_entry = self
+                let index_entry = self
                     .terms
                     .entry(token.to_owned())
                     .or_insert_with(TermEntry::new);
-                let count = *term_frequencies.get(&token).unwrap_or(&0);
+                let count = *term_frequencies.get(token).unwrap_or(&0);
                 term_frequencies.insert(token.to_owned(), count + 1);
 
                 if count == 0 {
-                    self.trie.insert(&token, doc_id);
+                    self.trie.insert(token, doc_id);
                     index_entry.register(field.name.to_owned(), doc_id);
                 }
 
@@ -609,23 +1083,30 @@ impl FTSIndex {
             // After each field, bump by one to prevent accidental adjacency.
             self.term_id += 1;
 
+            let number_of_tokens = tokens.len() as u32;
             field.total_tokens += number_of_tokens;
             field.documents.insert(
                 doc_id,
-                DocumentEntry::new(number_of_tokens, term_frequencies),
+                DocumentEntry::new(number_of_tokens, start, term_frequencies),
             );
         }
 
-        for (token, prefix_size, closeness) in correlations {
-            self.correlate_word(&token[prefix_size as usize..], &token, closeness);
+        for &(ref token, prefix_size, closeness) in &document.correlations {
+            self.correlate_word(&token[prefix_size as usize..], token, closeness);
         }
 
+        Self::insert_title(&mut self.titles, &document.title, doc_id);
         self.documents.push(Document {
             _id: doc_id,
-            url: document.url,
+            url: document.url.to_owned(),
+            slug: document.slug.to_owned(),
 
-            title: document.title,
-            preview: document.preview,
+            title: document.title.to_owned(),
+            preview: document.preview.to_owned(),
+            text: Arc::clone(&document.text),
+            stored: document.stored.clone(),
+            metadata: document.metadata.clone(),
+            boost: document.boost,
 
             include_in_global_search,
             search_property: search_property.to_owned(),
@@ -642,6 +1123,8 @@ impl FTSIndex {
             field.compute_length_weight();
         }
 
+        self.speller.finish();
+
         for (&doc_id, url) in &self.id_to_url {
             let mut outgoing_neighbors_set = HashSet::new();
             if let Some(links) = self.link_graph.get(url) {
@@ -668,140 +1151,734 @@ impl FTSIndex {
             self.incoming_neighbors.insert(doc_id, incoming_neighbors);
         }
 
+        self.compute_pagerank();
         self.finished = time::get_time();
     }
 
-    fn collect_matches_from_trie<'a, I>(&self, terms: I) -> Vec<(DocID, Vec<&str>)>
-    where
-        I: iter::Iterator<Item = &'a String>,
-    {
-        let mut result_set = vec![];
-        for term in terms {
-            for (doc_id, terms) in self.trie.search(term) {
-                result_set.push((doc_id, terms.to_owned()));
+    /// Compute the PageRank of every document across the whole link graph.
+    /// Documents without outgoing links share their rank among all documents.
+    fn compute_pagerank(&mut self) {
+        let n = self.documents.len();
+        let mut ranks = vec![1.0 / n as f32; n];
+
+        for _ in 0..PAGERANK_ITERATIONS {
+            let mut next_ranks = vec![0.0; n];
+            let mut dangling_rank = 0.0;
+            for (i, rank) in ranks.iter().enumerate() {
+                match self.outgoing_neighbors.get(&DocID(i as u32)) {
+                    Some(neighbors) if !neighbors.is_empty() => {
+                        let share = rank / neighbors.len() as f32;
+                        for neighbor in neighbors {
+                            next_ranks[neighbor.usize()] += share;
+                        }
+                    }
+                    _ => dangling_rank += rank,
+                }
+            }
+
+            let base_rank = (1.0 - PAGERANK_DAMPING + PAGERANK_DAMPING * dangling_rank) / n as f32;
+            let mut change = 0.0;
+            for (rank, next_rank) in ranks.iter_mut().zip(next_ranks) {
+                let next_rank = base_rank + PAGERANK_DAMPING * next_rank;
+                change += (next_rank - *rank).abs();
+                *rank = next_rank;
+            }
+
+            if change < PAGERANK_THRESHOLD {
+                break;
             }
         }
 
-        result_set
+        self.pagerank = ranks;
     }
 
-    pub fn search(&self, query: &Query) -> Vec<&Document> {
-        let search_properties: HashSet<&str> = query
-            .search_properties
+    /// Return how close together the given stemmed query terms appear in a
+    /// matching document, from 0 to 1: the mean over each pair of terms of the
+    /// reciprocal of the shortest distance between them.
+    fn proximity(&self, search_match: &SearchMatch, stems: &[String]) -> f32 {
+        let positions: Vec<Vec<TokenID>> = stems
             .iter()
-            .map(
-                |property| match self.search_property_aliases.get(*property) {
-                    Some(p) => p,
-                    None => *property,
-                },
-            )
+            .map(|stem| {
+                let mut positions: Vec<TokenID> = search_match
+                    .terms
+                    .iter()
+                    .filter(|term| term.starts_with(stem.as_str()))
+                    .filter_map(|term| self.terms.get(term))
+                    .filter_map(|term_entry| term_entry.positions.get(&search_match._id))
+                    .flat_map(|positions| positions.iter().cloned())
+                    .collect();
+                positions.sort();
+                positions
+            })
             .collect();
 
-        let mut match_set: HashMap<DocID, SearchMatch> = HashMap::new();
-        let original_terms: HashSet<_> = query.terms.iter().collect();
-        let original_terms: Vec<_> = original_terms.into_iter().collect();
-        let stemmed_terms = self.collect_correlations(&original_terms);
+        // Terms in different fields aren't near each other, however close
+        // their positions are
+        let entries: Vec<&DocumentEntry> = self
+            .fields
+            .iter()
+            .filter_map(|field| field.documents.get(&search_match._id))
+            .collect();
+        let in_field = |positions: &[TokenID], entry: &DocumentEntry| -> Vec<TokenID> {
+            positions.iter().cloned().filter(|&p| entry.contains(p)).collect()
+        };
 
-        let mut keys = stemmed_terms.keys();
-        for (doc_id, ref terms) in self.collect_matches_from_trie(&mut keys) {
-            let doc: &Document = &self.documents[doc_id.usize()];
-            if search_properties.is_empty() {
-                if !doc.include_in_global_search {
-                    continue;
+        let mut total = 0.0;
+        let mut pairs = 0;
+        for i in 0..positions.len() {
+            for other in &positions[i + 1..] {
+                pairs += 1;
+                let distance = entries
+                    .iter()
+                    .filter_map(|entry| {
+                        min_distance(&in_field(&positions[i], entry), &in_field(other, entry))
+                    })
+                    .min();
+                if let Some(distance) = distance {
+                    total += 1.0 / distance as f32;
                 }
-            } else if !search_properties.contains::<str>(&doc.search_property) {
-                continue;
-            };
+            }
+        }
 
-            for &term in terms {
-                let term_entry = &self.terms[term];
+        total / pairs as f32
+    }
 
-                let mut term_relevancy_score: f32 = 0.0;
-                for field in &self.fields {
-                    let doc_entry = match field.documents.get(&doc_id) {
-                        Some(e) => e,
-                        None => continue,
-                    };
+    /// Return the names of the indexed fields.
+    pub fn field_names(&self) -> Vec<&str> {
+        self.fields.iter().map(|f| f.name.as_str()).collect()
+    }
 
-                    let term_weight = *(stemmed_terms.get(term).unwrap_or(&0.1));
-                    let term_frequency_in_doc =
-                        *(doc_entry.term_frequencies.get(term).unwrap_or(&0));
-                    let term_probability =
-                        *(term_entry.times_appeared.get(&field.name).unwrap_or(&0)) as f32
-                            / cmp::max(field.total_tokens, 500) as f32;
-
-                    // Larger fields yield larger scores, but we want fields to have roughly
-                    // equal weight. field.lengthWeight is stupid, but yields good results.
-                    term_relevancy_score += dirichlet_plus(
-                        term_weight,
-                        term_frequency_in_doc,
-                        term_probability,
-                        doc_entry.len,
-                        original_terms.len() as u32,
-                    ) * field.weight
-                        * field.length_weight;
-                }
+    /// Return true if this index was built with the given fields.
+    pub fn has_fields(&self, fields: &[Field]) -> bool {
+        self.fields.len() == fields.len()
+            && self
+                .fields
+                .iter()
+                .zip(fields)
+                .all(|(a, b)| a.has_same_definition(b))
+    }
 
-                let search_match = match_set
-                    .entry(doc_id)
-                    .or_insert_with(|| SearchMatch::new(doc_id));
-                search_match.relevancy_score += term_relevancy_score;
-                search_match.terms.insert(term.to_owned());
+    /// Suggest corrections for any query terms that match nothing in the index.
+    /// Terms that are a prefix of some indexed term are left alone, since they
+    /// already yield results.
+    pub fn spelling_corrections(&self, query: &Query) -> HashMap<String, String> {
+        let mut corrections = HashMap::new();
+
+        for term in &query.terms {
+            if is_stop_word(term) || self.trie.has_prefix(&stem(term)) {
+                continue;
+            }
+
+            if let Some(correction) = self.speller.correct(term) {
+                corrections.insert(term.to_owned(), correction.to_owned());
             }
         }
 
-        // Create a root set of the core relevant results
-        let root_set = match_set.drain().map(|(_, v)| v);
-        let mut root_set: Vec<_> = if query.phrases.is_empty() {
-            root_set.collect()
-        } else {
-            root_set
-                .filter(|search_match| {
-                    let mut tokens = HashMap::new();
-                    for term in &search_match.terms {
-                        let term_entry = match self.terms.get(term) {
-                            Some(v) => v,
-                            None => return false,
-                        };
-
-                        let positions = match term_entry.positions.get(&search_match._id) {
-                            Some(v) => v,
-                            None => return false,
-                        };
-
-                        tokens.insert(term, positions.as_slice());
-                    }
-                    query.check_phrases(&tokens)
-                })
-                .collect()
-        };
+        corrections
+    }
 
-        // Expand our root set's neighbors to create a base set: the set of all
-        // relevant pages, as well as pages that link TO or are linked FROM those pages.
-        let root_ids: Vec<DocID> = root_set.iter().map(|m| m._id).collect();
-        let mut match_set = MatchSet::new();
-        for search_match in root_set.drain(..) {
-            match_set.insert(search_match, self);
+    /// Rebuild the lookup structures that are derived from the rest of the index
+    /// rather than stored in a snapshot.
+    pub fn rebuild_lookup_tables(&mut self) {
+        self.trie = Trie::new();
+        for (term, term_entry) in &self.terms {
+            for &doc_id in &term_entry.docs {
+                self.trie.insert(term, doc_id);
+            }
+        }
+
+        self.titles = Trie::new();
+        for doc in &self.documents {
+            Self::insert_title(&mut self.titles, &doc.title, doc._id);
+        }
+
+        self.speller.finish();
+    }
+
+    fn insert_title(titles: &mut Trie, title: &str, doc_id: DocID) {
+        let title = title.to_lowercase();
+        let word_starts = title.match_indices(' ').map(|(i, _)| i + 1);
+        for start in iter::once(0).chain(word_starts) {
+            titles.insert(&title[start..], doc_id);
+        }
+    }
+
+    /// Match each term, along with up to the given number of indexed terms
+    /// that it is a prefix of.
+    fn collect_matches_from_trie<'a, I>(&self, terms: I) -> Vec<(DocID, &'a String, Vec<&str>)>
+    where
+        I: iter::Iterator<Item = (&'a String, usize)>,
+    {
+        let mut result_set = vec![];
+        for (term, max_expansions) in terms {
+            for (doc_id, terms) in self.trie.search_expanded(term, max_expansions) {
+                result_set.push((doc_id, term, terms.to_owned()));
+            }
         }
 
-        match_set.finish(&root_ids);
+        result_set
+    }
 
-        // Run HITS to re-sort our results based on authority
-        match_set
-            .hits(0.00001, 200)
+    fn resolve_search_properties<'a>(&'a self, search_properties: &[&'a str]) -> HashSet<&'a str> {
+        search_properties
             .iter()
-            .map(|id| &self.documents[id.usize()])
+            .map(
+                |property| match self.search_property_aliases.get(*property) {
+                    Some(p) => p,
+                    None => *property,
+                },
+            )
             .collect()
     }
+
+    /// Return true if a document may be returned when searching the given
+    /// properties, or globally if none are given.
+    fn is_searchable(doc: &Document, search_properties: &HashSet<&str>) -> bool {
+        if search_properties.is_empty() {
+            doc.include_in_global_search
+        } else {
+            search_properties.contains::<str>(&doc.search_property)
+        }
+    }
+
+    /// Find the words of a document's field that were indexed as one of
+    /// `terms`, using the positions stored for each term. `text` must be the
+    /// field's contents as they were indexed.
+    pub fn field_hits(
+        &self,
+        doc: &Document,
+        field_name: &str,
+        text: &str,
+        terms: &HashSet<String>,
+    ) -> Vec<Hit> {
+        let entry = match self
+            .fields
+            .iter()
+            .find(|field| field.name == field_name)
+            .and_then(|field| field.documents.get(&doc._id))
+        {
+            Some(entry) => entry,
+            None => return vec![],
+        };
+
+        let mut positions = vec![];
+        for term in terms {
+            let term_positions = match self.terms.get(term) {
+                Some(term_entry) => term_entry.positions.get(&doc._id),
+                None => None,
+            };
+
+            for &position in term_positions.into_iter().flat_map(|p| p.iter()) {
+                if entry.contains(position) {
+                    positions.push(((position - entry.start) as usize, term));
+                }
+            }
+        }
+
+        if positions.is_empty() {
+            return vec![];
+        }
+
+        positions.sort();
+
+        // Tokenize the same way as when indexing, to find where each token came from
+        let spans: Vec<_> = tokenize_spans(text, true)
+            .into_iter()
+            .filter(|&(ref token, _)| !is_stop_word(token))
+            .map(|(_, span)| span)
+            .collect();
+
+        let mut hits: Vec<Hit> = vec![];
+        for (i, term) in positions {
+            let (start, end) = match spans.get(i) {
+                Some(&span) => span,
+                None => continue,
+            };
+
+            // Every part of a dotted word shares the word's span
+            if hits.last().map(|hit| hit.start) == Some(start) {
+                continue;
+            }
+
+            hits.push(Hit {
+                start,
+                end,
+                term: term.to_owned(),
+            });
+        }
+
+        hits
+    }
+
+    /// Return the unstemmed words behind an indexed token in the given search
+    /// properties, or in every property if none are given, along with how
+    /// often each appears.
+    fn surface_forms<'a>(
+        &'a self,
+        token: &'a str,
+        search_properties: &HashSet<&str>,
+    ) -> HashMap<&'a str, u32> {
+        let mut forms: HashMap<&str, u32> = HashMap::new();
+        for (property, property_forms) in &self.surface_forms {
+            if !search_properties.is_empty() && !search_properties.contains::<str>(property) {
+                continue;
+            }
+
+            if let Some(token_forms) = property_forms.get(token) {
+                for (form, &count) in token_forms {
+                    *forms.entry(form).or_insert(0) += count;
+                }
+            }
+        }
+
+        // Unstemmed tokens are their own surface form
+        if forms.is_empty() {
+            forms.insert(token, 1);
+        }
+
+        forms
+    }
+
+    /// Complete the final word of a partially typed query, preferring words
+    /// found in many documents and in heavily weighted fields, and find
+    /// documents whose titles contain a word beginning with the query.
+    pub fn suggest<'a>(
+        &'a self,
+        query_string: &str,
+        search_properties: &[&str],
+        limit: usize,
+    ) -> Suggestions<'a> {
+        let search_properties = self.resolve_search_properties(search_properties);
+        let query_string = query_string.trim_left();
+        let head = query_string.trim_right_matches(|c: char| !c.is_whitespace());
+        let partial = query_string[head.len()..].to_lowercase();
+
+        let mut completions = vec![];
+        if partial.chars().count() >= MIN_SUGGESTION_LENGTH {
+            // A word's stem may be longer or shorter than the partial word
+            let mut candidates: HashMap<&str, &HashSet<DocID>> = HashMap::new();
+            candidates.extend(self.trie.tokens_with_prefix(&partial));
+            candidates.extend(self.trie.tokens_with_prefix(&stem(&partial)));
+            for (i, _) in partial.char_indices().skip(1) {
+                if let Some(docs) = self.trie.get(&partial[..i]) {
+                    candidates.insert(&partial[..i], docs);
+                }
+            }
+
+            // Only weigh the most widespread terms
+            let mut candidates: Vec<_> = candidates.into_iter().collect();
+            candidates.sort_by(|a, b| b.1.len().cmp(&a.1.len()).then_with(|| a.0.cmp(b.0)));
+            candidates.truncate(MAX_SUGGESTION_CANDIDATES);
+
+            let mut scores: HashMap<&str, f32> = HashMap::new();
+            for (token, docs) in candidates {
+                let weight: f32 = docs
+                    .iter()
+                    .filter(|doc_id| {
+                        Self::is_searchable(&self.documents[doc_id.usize()], &search_properties)
+                    })
+                    .map(|doc_id| {
+                        self.fields
+                            .iter()
+                            .filter(|field| match field.documents.get(doc_id) {
+                                Some(entry) => entry.term_frequencies.contains_key(token),
+                                None => false,
+                            })
+                            .map(|field| field.weight)
+                            .fold(0.0, f32::max)
+                    })
+                    .sum();
+
+                if weight == 0.0 {
+                    continue;
+                }
+
+                let forms = self.surface_forms(token, &search_properties);
+                let total: u32 = forms.values().sum();
+
+                for (form, count) in forms {
+                    if form.starts_with(&partial) {
+                        let score = scores.entry(form).or_insert(0.0);
+                        *score = score.max(weight * count as f32 / total as f32);
+                    }
+                }
+            }
+
+            let mut scores: Vec<_> = scores.into_iter().collect();
+            scores.sort_by(|a, b| cmp_scores(b.1, a.1).then_with(|| a.0.cmp(b.0)));
+            completions = scores
+                .into_iter()
+                .take(limit)
+                .map(|(form, _)| format!("{}{}", head, form))
+                .collect();
+        }
+
+        let mut titles = vec![];
+        let needle = query_string.to_lowercase();
+        if needle.chars().count() >= MIN_SUGGESTION_LENGTH {
+            let mut doc_ids: HashSet<DocID> = HashSet::new();
+            for (_, ids) in self.titles.tokens_with_prefix(&needle) {
+                doc_ids.extend(ids);
+            }
+
+            titles = doc_ids
+                .into_iter()
+                .map(|doc_id| &self.documents[doc_id.usize()])
+                .filter(|doc| Self::is_searchable(doc, &search_properties))
+                .collect();
+
+            // Prefer the most authoritative pages
+            titles.sort_by(|a, b| {
+                let a_rank = *self.pagerank.get(a._id.usize()).unwrap_or(&0.0);
+                let b_rank = *self.pagerank.get(b._id.usize()).unwrap_or(&0.0);
+                cmp_scores(b_rank, a_rank).then_with(|| a._id.cmp(&b._id))
+            });
+            titles.truncate(limit);
+        }
+
+        Suggestions {
+            completions,
+            titles,
+        }
+    }
+
+    /// Return up to `options.limit` results, skipping the `options.offset`
+    /// best matches.
+    pub fn search<'a>(&'a self, query: &Query, options: &SearchOptions) -> SearchResults<'a> {
+        let search_properties = self.resolve_search_properties(query.search_properties);
+
+        let ranking = options.ranking.as_ref().unwrap_or(&self.ranking);
+        let scorer = ranking.scorer.scorer();
+        let mut match_set: HashMap<DocID, SearchMatch> = HashMap::new();
+        let original_terms: HashSet<_> = query.terms.iter().collect();
+        let original_terms: Vec<_> = original_terms.into_iter().collect();
+
+        // Terms scoped to particular fields don't pull in correlated terms
+        let unscoped_terms: Vec<_> = original_terms
+            .iter()
+            .cloned()
+            .filter(|term| !query.term_fields.contains_key(*term))
+            .collect();
+        let mut stemmed_terms = self.collect_correlations(&unscoped_terms);
+        let mut term_fields: HashMap<String, &BTreeSet<String>> = HashMap::new();
+        for (term, fields) in &query.term_fields {
+            let stemmed = stem(term);
+            stemmed_terms.insert(stemmed.to_owned(), 1.0);
+            term_fields.insert(stemmed, fields);
+        }
+
+        // Fall back to similar terms for any query term with few matches
+        let mut fuzzy_matches: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        let mut fuzzy_terms: HashSet<&str> = HashSet::new();
+        if ranking.fuzzy_weight > 0.0 {
+            for &term in &original_terms {
+                let stemmed = stem(term);
+                if !is_correctable(term)
+                    || self.trie.search(&stemmed).len() >= ranking.fuzzy_threshold as usize
+                {
+                    continue;
+                }
+
+                let mut matches =
+                    self.trie.fuzzy_search(&stemmed, max_edits(term), MAX_FUZZY_CANDIDATES);
+                matches.sort_by_key(|&(token, distance)| (distance, token));
+                for (token, _) in matches.into_iter().take(MAX_FUZZY_MATCHES) {
+                    if stemmed_terms.contains_key(token) {
+                        continue;
+                    }
+
+                    stemmed_terms.insert(token.to_owned(), ranking.fuzzy_weight);
+                    if let Some(fields) = query.term_fields.get(term) {
+                        term_fields.insert(token.to_owned(), fields);
+                    }
+
+                    fuzzy_terms.insert(token);
+
+                    // Report the word most often behind the stem, not the stem itself
+                    let form = self
+                        .surface_forms(token, &search_properties)
+                        .into_iter()
+                        .max_by(|a, b| a.1.cmp(&b.1).then_with(|| b.0.cmp(a.0)))
+                        .map(|(form, _)| form)
+                        .unwrap_or(token);
+                    let forms = fuzzy_matches
+                        .entry(term.to_owned())
+                        .or_insert_with(Vec::new);
+                    if !forms.iter().any(|f| f == form) {
+                        forms.push(form.to_owned());
+                    }
+                }
+            }
+        }
+
+        // Fuzzy matches are never expanded
+        let prefix_term = query.prefix_term.as_ref().map(|term| stem(term));
+        let max_expansions = |key: &String| {
+            let expand = match ranking.prefix_expansion {
+                PrefixExpansion::All => true,
+                PrefixExpansion::Last => prefix_term.as_ref() == Some(key),
+                PrefixExpansion::None => false,
+            };
+
+            if expand
+                && !fuzzy_terms.contains(key.as_str())
+                && key.chars().count() >= ranking.prefix_min_length
+            {
+                ranking.prefix_max_expansions
+            } else {
+                0
+            }
+        };
+
+        let mut keys: Vec<_> = stemmed_terms.keys().collect();
+        keys.sort();
+        let keys = keys.into_iter().map(|key| (key, max_expansions(key)));
+        let mut expansions: HashMap<&str, HashMap<DocID, Vec<&str>>> = HashMap::new();
+        for (doc_id, key, ref terms) in self.collect_matches_from_trie(keys) {
+            let doc = &self.documents[doc_id.usize()];
+            if !Self::is_searchable(doc, &search_properties) || !options.filter.matches(doc) {
+                continue;
+            }
+
+            // Required terms may be satisfied by the same expansions used for scoring
+            if query.is_constrained() {
+                expansions
+                    .entry(key)
+                    .or_insert_with(HashMap::new)
+                    .insert(doc_id, terms.to_owned());
+            }
+
+            for &term in terms {
+                let term_entry = &self.terms[term];
+                let term_weight = *(stemmed_terms.get(term).unwrap_or(&ranking.prefix_weight));
+                let term_stats = TermStats {
+                    weight: term_weight,
+                    query_length: original_terms.len() as u32,
+                    document_frequency: term_entry.document_frequency(),
+                    document_count: self.documents.len() as u32,
+                };
+
+                let scope = term_fields.get(key);
+                let mut field_names = vec![];
+                let mut field_stats = vec![];
+                for field in &self.fields {
+                    if scope.map(|fields| !fields.contains(&field.name)).unwrap_or(false) {
+                        continue;
+                    }
+
+                    let doc_entry = match field.documents.get(&doc_id) {
+                        Some(e) => e,
+                        None => continue,
+                    };
+
+                    field_names.push(&field.name);
+                    field_stats.push(FieldStats {
+                        weight: field.weight,
+                        length_weight: field.length_weight,
+                        term_frequency: *(doc_entry.term_frequencies.get(term).unwrap_or(&0)),
+                        term_documents: *(term_entry.times_appeared.get(&field.name).unwrap_or(&0)),
+                        length: doc_entry.len,
+                        average_length: field.total_tokens as f32 / field.documents.len() as f32,
+                        total_tokens: field.total_tokens,
+                    });
+                }
+
+                if scope.is_some() && field_stats.iter().all(|f| f.term_frequency == 0) {
+                    continue;
+                }
+
+                let field_scores = scorer.score(&term_stats, &field_stats, ranking);
+                let term_relevancy_score: f32 = field_scores.iter().sum();
+
+                let search_match = match_set
+                    .entry(doc_id)
+                    .or_insert_with(|| SearchMatch::new(doc_id));
+                search_match.relevancy_score += term_relevancy_score;
+                search_match.terms.insert(term.to_owned());
+
+                if options.explain {
+                    search_match.term_explanations.push(TermExplanation {
+                        term: term.to_owned(),
+                        weight: term_weight,
+                        fields: field_names
+                            .into_iter()
+                            .cloned()
+                            .zip(field_scores)
+                            .collect(),
+                    });
+                }
+            }
+        }
+
+        // Create a root set of the core relevant results, enforcing any
+        // required and excluded clauses
+        let root_set = match_set.drain().map(|(_, v)| v);
+        let mut root_set: Vec<_> = if !query.is_constrained() {
+            root_set.collect()
+        } else {
+            root_set
+                .filter(|search_match| {
+                    query.matches(&CandidateDocument {
+                        index: self,
+                        doc_id: search_match._id,
+                        expansions: &expansions,
+                    })
+                })
+                .collect()
+        };
+
+        // Boost results where the query's terms appear close together
+        let mut query_stems: Vec<String> = query
+            .terms
+            .iter()
+            .filter(|term| !is_stop_word(term))
+            .map(|term| stem(term))
+            .collect();
+        query_stems.sort();
+        query_stems.dedup();
+        if ranking.proximity_weight > 0.0 && query_stems.len() > 1 {
+            for search_match in &mut root_set {
+                if search_match.relevancy_score > 0.0 {
+                    let proximity = self.proximity(search_match, &query_stems);
+                    search_match.proximity =
+                        search_match.relevancy_score * ranking.proximity_weight * proximity;
+                    search_match.relevancy_score += search_match.proximity;
+                }
+            }
+        }
+
+        for search_match in &mut root_set {
+            let doc = &self.documents[search_match._id.usize()];
+            search_match.boost = doc.boost * ranking.url_boost(&doc.url);
+        }
+
+        let mut match_set = MatchSet::new();
+        let (authority_scores, hub_scores) = match ranking.link_analysis {
+            LinkAnalysis::Hits => {
+                // Expand our root set's neighbors to create a base set: the set of all
+                // relevant pages, as well as pages that link TO or are linked FROM those pages.
+                let root_ids: Vec<DocID> = root_set.iter().map(|m| m._id).collect();
+                for search_match in root_set.drain(..) {
+                    match_set.insert(search_match, self);
+                }
+
+                match_set.finish(&root_ids);
+
+                // Run HITS to re-sort our results based on authority
+                match_set.hits(ranking)
+            }
+            LinkAnalysis::PageRank => {
+                let authority_scores = root_set
+                    .iter()
+                    .map(|m| (m._id, self.pagerank[m._id.usize()]))
+                    .collect();
+                match_set.matches.extend(root_set.drain(..).map(|m| (m._id, m)));
+                (authority_scores, HashMap::new())
+            }
+            LinkAnalysis::None => {
+                match_set.matches.extend(root_set.drain(..).map(|m| (m._id, m)));
+                (HashMap::new(), HashMap::new())
+            }
+        };
+
+        let ranked = match_set.rank(authority_scores, &hub_scores, ranking);
+
+        // Put any results pinned for this query first, if this search may return them
+        let mut pinned_ids: Vec<DocID> = vec![];
+        for url in self.pinned.get(&query.terms) {
+            let mut url = url.to_owned();
+            normalize_url(&mut url);
+            if let Some(&id) = self.url_to_id.get(&url) {
+                let doc = &self.documents[id.usize()];
+                let candidate = CandidateDocument {
+                    index: self,
+                    doc_id: id,
+                    expansions: &expansions,
+                };
+
+                if Self::is_searchable(doc, &search_properties)
+                    && options.filter.matches(doc)
+                    && query.matches(&candidate)
+                    && !pinned_ids.contains(&id)
+                {
+                    pinned_ids.push(id);
+                }
+            }
+        }
+
+        let ranked: Vec<DocID> = pinned_ids
+            .iter()
+            .cloned()
+            .chain(ranked.into_iter().filter(|id| !pinned_ids.contains(id)))
+            .collect();
+        let total = ranked.len();
+
+        let mut facets = BTreeMap::new();
+        if options.facets {
+            for id in &ranked {
+                let search_property = self.documents[id.usize()].search_property.as_str();
+                *facets.entry(search_property).or_insert(0) += 1;
+            }
+        }
+
+        let results = ranked
+            .iter()
+            .skip(options.offset)
+            .take(options.limit)
+            .map(|id| {
+                let mut search_match = match_set
+                    .matches
+                    .remove(id)
+                    .unwrap_or_else(|| SearchMatch::new(*id));
+                SearchResult {
+                    document: &self.documents[id.usize()],
+                    explanation: if options.explain {
+                        Some(search_match.explain())
+                    } else {
+                        None
+                    },
+                    terms: search_match.terms,
+                    pinned: pinned_ids.contains(id),
+                }
+            })
+            .collect();
+
+        SearchResults {
+            results,
+            total,
+            fuzzy_matches,
+            facets,
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// A document with the given text, at a URL derived from its slug.
+    fn doc(slug: &str, text: &str) -> ManifestDocument {
+        ManifestDocument {
+            slug: slug.to_owned(),
+            title: "".to_owned(),
+            tags: "".to_owned(),
+            headings: vec![],
+            links: vec![],
+            extra: HashMap::new(),
+            metadata: HashMap::new(),
+            boost: None,
+            text: text.to_owned(),
+            preview: "".to_owned(),
+            url: format!("https://example.com/{}", slug),
+        }
+    }
+
     #[test]
     fn test_fts() {
-        let mut index = FTSIndex::new(vec![Field::new("text", 1.0), Field::new("title", 10.0)]);
+        let mut index = FTSIndex::new(
+            vec![Field::new("text", 1.0), Field::new("title", 10.0)],
+            RankingConfig::default(),
+        );
 
         index.add(
             ManifestDocument {
@@ -810,6 +1887,9 @@ mod tests {
                 tags: "".to_owned(),
                 headings: vec![],
                 links: vec!["https://en.wikipedia.org/wiki/Red_fox".to_owned()],
+                extra: HashMap::new(),
+                metadata: HashMap::new(),
+                boost: None,
                 text: r#"Foxes are small-to-medium-sized, omnivorous mammals belonging to several genera of the family Canidae. Foxes have a flattened skull, upright triangular ears, a pointed, slightly upturned snout, and a long bushy tail (or brush)."#.to_owned(),
                 preview: "".to_owned(),
                 url: "https://en.wikipedia.org/wiki/Fox".to_owned(),
@@ -822,6 +1902,9 @@ mod tests {
                 tags: "".to_owned(),
                 headings: vec![],
                 links: vec![],
+                extra: HashMap::new(),
+                metadata: HashMap::new(),
+                boost: None,
                 text: r#"The red fox (Vulpes vulpes), largest of the true foxes, has the greatest geographic range of all members of the Carnivora order, being present across the entire Northern Hemisphere from the Arctic Circle to North Africa, North America and Eurasia. It is listed as least concern by the IUCN.[1] Its range has increased alongside human expansion, having been introduced to Australia, where it is considered harmful to native mammals and bird populations. Due to its presence in Australia, it is included among the list of the "world's 100 worst invasive species"."#.to_owned(),
                 preview: "".to_owned(),
                 url: "https://en.wikipedia.org/wiki/Red_fox".to_owned(),
@@ -833,12 +1916,598 @@ mod tests {
             tags: "".to_owned(),
             headings: vec![],
             links: vec![],
+            extra: HashMap::new(),
+            metadata: HashMap::new(),
+            boost: None,
             text: r#"Omnivore /ˈɒmnivɔər/ is a consumption classification for animals that have the capability to obtain chemical energy and nutrients from materials originating from plant and animal origin. Often, omnivores also have the ability to incorporate food sources such as algae, fungi, and bacteria into their diet as well."#.to_owned(),
             preview: "".to_owned(),
             url: "https://en.wikipedia.org/wiki/Omnivore".to_owned(),
         }, true, "property".to_owned());
 
         index.finish();
-        index.search(&Query::new("fox carnivora", &[]));
+        let results = index.search(
+            &Query::new("fox carnivora", &[], &[]),
+            &SearchOptions {
+                explain: true,
+                ..SearchOptions::default()
+            },
+        );
+
+        let explanation = results.results[0].explanation.as_ref().unwrap();
+        let terms: Vec<_> = explanation.terms.iter().map(|t| t.term.as_str()).collect();
+        assert!(terms.contains(&"fox"));
+        let relevancy: f32 = explanation
+            .terms
+            .iter()
+            .flat_map(|t| t.fields.values())
+            .sum();
+        assert!((relevancy + explanation.proximity - explanation.relevancy).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_pagination() {
+        let mut index = FTSIndex::new(
+            vec![Field::new("text", 1.0), Field::new("title", 10.0)],
+            RankingConfig::default(),
+        );
+        for i in 0..5 {
+            index.add(
+                ManifestDocument {
+                    title: "Fox".to_owned(),
+                    ..doc(
+                        &format!("fox-{}", i),
+                        "Foxes are small-to-medium-sized, omnivorous mammals.",
+                    )
+                },
+                true,
+                if i % 2 == 0 { "even" } else { "odd" }.to_owned(),
+            );
+        }
+        index.finish();
+
+        // Every document scores the same, so ties must be broken consistently
+        let query = Query::new("fox", &[], &[]);
+        let mut seen = vec![];
+        for offset in &[0, 2, 4] {
+            let page = index.search(
+                &query,
+                &SearchOptions {
+                    offset: *offset,
+                    limit: 2,
+                    ..SearchOptions::default()
+                },
+            );
+            assert!(page.results.iter().all(|r| r.explanation.is_none()));
+            assert_eq!(page.total, 5);
+            seen.extend(page.results.iter().map(|r| r.document._id));
+        }
+        assert_eq!(seen, (0..5).map(DocID).collect::<Vec<_>>());
+
+        // Facets count every match, not just the requested page
+        let page = index.search(&query, &SearchOptions::default());
+        assert!(page.facets.is_empty());
+        let page = index.search(
+            &query,
+            &SearchOptions {
+                limit: 1,
+                facets: true,
+                ..SearchOptions::default()
+            },
+        );
+        assert_eq!(page.results.len(), 1);
+        assert_eq!(page.facets, btreemap!{"even" => 3, "odd" => 2});
+
+        let options = SearchOptions {
+            offset: 5,
+            ..SearchOptions::default()
+        };
+        assert!(index.search(&query, &options).results.is_empty());
+    }
+
+    #[test]
+    fn test_field_scope() {
+        let mut index = FTSIndex::new(
+            vec![Field::new("text", 1.0), Field::new("title", 10.0)],
+            RankingConfig::default(),
+        );
+        for &(slug, title, text) in &[
+            ("fox", "Fox", "The red fox is the largest of the true foxes."),
+            ("wolf", "Wolf", "Wolves sometimes hunt the red fox."),
+        ] {
+            index.add(
+                ManifestDocument {
+                    title: title.to_owned(),
+                    ..doc(slug, text)
+                },
+                true,
+                "property".to_owned(),
+            );
+        }
+        index.finish();
+
+        let search = |query_string: &str| -> Vec<String> {
+            let query = Query::new(query_string, &[], &index.field_names());
+            let options = SearchOptions {
+                explain: true,
+                ..SearchOptions::default()
+            };
+            let results = index.search(&query, &options);
+            for result in &results.results {
+                for term in &result.explanation.as_ref().unwrap().terms {
+                    if query_string.starts_with("title:") {
+                        assert_eq!(term.fields.keys().collect::<Vec<_>>(), vec!["title"]);
+                    }
+                }
+            }
+
+            results.results.iter().map(|r| r.document.slug.to_owned()).collect()
+        };
+
+        assert_eq!(search("fox"), vec!["fox", "wolf"]);
+        assert_eq!(search("title:fox"), vec!["fox"]);
+        assert_eq!(search("+title:fox"), vec!["fox"]);
+        assert_eq!(search(r#"text:"red fox""#).len(), 2);
+        assert!(search(r#"title:"red fox""#).is_empty());
+        assert_eq!(search("fox -title:fox"), vec!["wolf"]);
+    }
+
+    #[test]
+    fn test_stored_fields() {
+        let signatures = Field::from_config(&FieldConfig {
+            name: "signatures".to_owned(),
+            weight: 2.0,
+            stemmed: false,
+            stored: true,
+        });
+        let mut index = FTSIndex::new(
+            vec![Field::new("text", 1.0), signatures],
+            RankingConfig::default(),
+        );
+        let mut document = doc("find", "Selects documents in a collection.");
+        document.extra.insert(
+            "signatures".to_owned(),
+            json!("db.collection.find(query, projection)"),
+        );
+        index.add(document, true, "property".to_owned());
+        index.finish();
+
+        let query = Query::new("projection", &[], &[]);
+        let results = index.search(&query, &SearchOptions::default());
+        let result = &results.results[0];
+        let text = result.document.field("signatures").unwrap();
+        let hits = index.field_hits(result.document, "signatures", text, &result.terms);
+        assert_eq!(
+            hits.iter().map(|hit| &text[hit.start..hit.end]).collect::<Vec<_>>(),
+            vec!["projection"]
+        );
+        assert_eq!(result.document.field("title"), Some(""));
+        assert_eq!(result.document.field("headings"), None);
+    }
+
+    #[test]
+    fn test_required_terms() {
+        let mut index = FTSIndex::new(vec![Field::new("text", 1.0)], RankingConfig::default());
+        for &(slug, text) in &[
+            ("fox", "The red fox hunts at night."),
+            ("foxglove", "Foxglove is a red flowering plant."),
+        ] {
+            index.add(
+                doc(slug, text),
+                true,
+                "property".to_owned(),
+            );
+        }
+        index.finish();
+
+        let search = |query_string: &str| -> Vec<String> {
+            let query = Query::new(query_string, &[], &index.field_names());
+            let results = index.search(&query, &SearchOptions::default());
+            results.results.iter().map(|r| r.document.slug.to_owned()).collect()
+        };
+
+        // Excluded terms must match exactly, not as a prefix
+        assert_eq!(search("red -fox"), vec!["foxglove"]);
+        assert!(search("red +fo").is_empty());
+    }
+
+    #[test]
+    fn test_filter() {
+        assert!("version".parse::<Filter>().is_err());
+        assert!(":4.0".parse::<Filter>().is_err());
+        assert_eq!(
+            "version:4.0, version:3.6,product:Atlas".parse(),
+            Ok(Filter {
+                constraints: btreemap!{
+                    "product".to_owned() => btreeset!{"atlas".to_owned()},
+                    "version".to_owned() => btreeset!{"3.6".to_owned(), "4.0".to_owned()},
+                },
+            })
+        );
+
+        let mut index = FTSIndex::new(vec![Field::new("text", 1.0)], RankingConfig::default());
+        for &(slug, ref metadata) in &[
+            ("atlas-4.0", json!({"product": "Atlas", "version": 4.0})),
+            ("atlas-3.6", json!({"product": "Atlas", "version": [3.6, "3.6.1"]})),
+            ("compass", json!({"product": "Compass", "deprecated": true})),
+            ("plain", json!({})),
+        ] {
+            let metadata = metadata.as_object().unwrap().clone().into_iter().collect();
+            index.add(
+                ManifestDocument {
+                    metadata,
+                    ..doc(slug, "Connecting to a cluster")
+                },
+                true,
+                "property".to_owned(),
+            );
+        }
+        index.finish();
+
+        let search = |filter: &str| -> Vec<String> {
+            let query = Query::new("cluster", &[], &[]);
+            let options = SearchOptions {
+                filter: filter.parse().unwrap(),
+                ..SearchOptions::default()
+            };
+            let mut slugs: Vec<_> = index
+                .search(&query, &options)
+                .results
+                .iter()
+                .map(|r| r.document.slug.to_owned())
+                .collect();
+            slugs.sort();
+            slugs
+        };
+
+        // Values of the same attribute are alternatives; different attributes must all match
+        assert_eq!(search("product:atlas"), vec!["atlas-3.6", "atlas-4.0"]);
+        assert_eq!(search("version:4.0,version:3.6.1"), vec!["atlas-3.6", "atlas-4.0"]);
+        assert_eq!(search("product:atlas,version:3.6"), vec!["atlas-3.6"]);
+        assert_eq!(search("product:compass,version:4.0"), Vec::<String>::new());
+        assert_eq!(search("deprecated:true"), vec!["compass"]);
+    }
+
+    #[test]
+    fn test_pinned() {
+        let mut index = FTSIndex::new(vec![Field::new("text", 1.0)], RankingConfig::default());
+        for &(slug, text, search_property) in &[
+            ("fox", "The red fox is the largest of the true foxes.", "canines"),
+            ("wolf", "Wolves sometimes hunt the red fox.", "canines"),
+            ("cat", "Cats are carnivores, like foxes.", "felines"),
+            ("badger", "Badgers dig setts.", "mustelids"),
+        ] {
+            index.add(
+                ManifestDocument {
+                    url: format!("https://example.com/{}/index.html", slug),
+                    ..doc(slug, text)
+                },
+                true,
+                search_property.to_owned(),
+            );
+        }
+        index.finish();
+        index.pinned = PinnedResults::new(btreemap!{
+            "Red Foxes".to_owned() => vec![
+                "https://example.com/cat/".to_owned(),
+                "https://example.com/wolf/index.html".to_owned(),
+                "https://example.com/badger/".to_owned(),
+                "https://example.com/missing/".to_owned(),
+            ],
+        }).unwrap();
+
+        let search = |query_string: &str, search_properties: &[&str]| -> Vec<(String, bool)> {
+            let query = Query::new(query_string, search_properties, &[]);
+            index
+                .search(&query, &SearchOptions::default())
+                .results
+                .iter()
+                .map(|r| (r.document.slug.to_owned(), r.pinned))
+                .collect()
+        };
+
+        // Pinned documents come first, in order, whether or not they match
+        let pinned = |slug: &str| (slug.to_owned(), true);
+        let unpinned = |slug: &str| (slug.to_owned(), false);
+        assert_eq!(
+            search("red fox", &[]),
+            vec![pinned("cat"), pinned("wolf"), pinned("badger"), unpinned("fox")]
+        );
+        assert_eq!(
+            search("fox red", &["canines"]),
+            vec![pinned("wolf"), unpinned("fox")]
+        );
+        assert_eq!(search("red", &[]), vec![unpinned("fox"), unpinned("wolf")]);
+
+        // ...but never in spite of a required or excluded term
+        assert_eq!(
+            search("red fox -carnivore", &[]),
+            vec![pinned("wolf"), pinned("badger"), unpinned("fox")]
+        );
+        assert_eq!(search("+red +fox", &[]), vec![pinned("wolf"), unpinned("fox")]);
+
+        // Pinned documents are counted, and paged like any other result
+        let options = SearchOptions {
+            facets: true,
+            ..SearchOptions::default()
+        };
+        let results = index.search(&Query::new("red fox", &[], &[]), &options);
+        assert_eq!(results.results.len(), 4);
+        assert_eq!(results.total, 4);
+        assert_eq!(
+            results.facets,
+            btreemap!["canines" => 2, "felines" => 1, "mustelids" => 1]
+        );
+
+        let options = SearchOptions {
+            offset: 3,
+            limit: 2,
+            ..SearchOptions::default()
+        };
+        let results = index.search(&Query::new("red fox", &[], &[]), &options);
+        assert_eq!(results.total, 4);
+        assert_eq!(results.results.len(), 1);
+        assert_eq!(results.results[0].document.slug, "fox");
+    }
+
+    #[test]
+    fn test_proximity() {
+        assert_eq!(min_distance(&[1, 10, 20], &[14, 30]), Some(4));
+        assert_eq!(min_distance(&[1], &[1]), None);
+        assert_eq!(min_distance(&[], &[1]), None);
+
+        let mut index = FTSIndex::new(
+            vec![Field::new("title", 1.0), Field::new("text", 1.0)],
+            RankingConfig::default(),
+        );
+        for &(slug, title, text) in &[
+            ("far", "", "A red panda is not a fox."),
+            ("near", "", "The red fox is not a panda."),
+            ("split", "Red", "Fox dens are dug into banks."),
+        ] {
+            index.add(
+                ManifestDocument {
+                    title: title.to_owned(),
+                    ..doc(slug, text)
+                },
+                true,
+                "property".to_owned(),
+            );
+        }
+        index.finish();
+
+        let options = SearchOptions {
+            explain: true,
+            ranking: Some(RankingConfig {
+                proximity_weight: 0.25,
+                ..RankingConfig::default()
+            }),
+            ..SearchOptions::default()
+        };
+        let results = index.search(&Query::new("red fox", &[], &[]), &options);
+        let rank = |slug: &str| {
+            results
+                .results
+                .iter()
+                .position(|r| r.document.slug == slug)
+                .unwrap()
+        };
+        let proximity = |slug: &str| {
+            let explanation = results.results[rank(slug)].explanation.as_ref();
+            explanation.unwrap().proximity
+        };
+        assert!(rank("near") < rank("far"));
+        assert!(proximity("near") > proximity("far"));
+
+        // The end of one field isn't near the start of the next
+        assert_eq!(proximity("split"), 0.0);
+        let results = index.search(&Query::new("red NEAR/2 fox", &[], &[]), &options);
+        assert_eq!(results.total, 1);
+
+        // Proximity is off by default
+        let options = SearchOptions {
+            explain: true,
+            ..SearchOptions::default()
+        };
+        let results = index.search(&Query::new("red fox", &[], &[]), &options);
+        assert!(
+            results
+                .results
+                .iter()
+                .all(|r| r.explanation.as_ref().unwrap().proximity == 0.0)
+        );
+    }
+
+    #[test]
+    fn test_fuzzy_matches() {
+        let mut index = FTSIndex::new(vec![Field::new("text", 1.0)], RankingConfig::default());
+        for &(slug, text) in &[
+            ("fox", "The red fox is an omnivore."),
+            ("wolf", "The grey wolf is a carnivore."),
+        ] {
+            index.add(
+                doc(slug, text),
+                true,
+                "property".to_owned(),
+            );
+        }
+        index.finish();
+
+        let options = SearchOptions {
+            explain: true,
+            ..SearchOptions::default()
+        };
+        let results = index.search(&Query::new("omnivor carnivor", &[], &[]), &options);
+        assert!(results.fuzzy_matches.is_empty());
+        assert_eq!(results.total, 2);
+
+        let results = index.search(&Query::new("omnivroe", &[], &[]), &options);
+        assert_eq!(results.fuzzy_matches, btreemap!["omnivroe".to_owned() => vec!["omnivore".to_owned()]]);
+        assert_eq!(results.total, 1);
+        let terms = &results.results[0].explanation.as_ref().unwrap().terms;
+        assert_eq!(terms[0].weight, RankingConfig::default().fuzzy_weight);
+
+        // Fuzzy matching can be turned off
+        let options = SearchOptions {
+            ranking: Some(RankingConfig {
+                fuzzy_weight: 0.0,
+                ..RankingConfig::default()
+            }),
+            ..SearchOptions::default()
+        };
+        assert_eq!(index.search(&Query::new("omnivroe", &[], &[]), &options).total, 0);
+    }
+
+    #[test]
+    fn test_prefix_expansion() {
+        let mut index = FTSIndex::new(vec![Field::new("text", 1.0)], RankingConfig::default());
+        for &(slug, text) in &[
+            ("fox", "The red fox is an omnivore."),
+            ("foxglove", "Foxglove is a flowering plant."),
+        ] {
+            index.add(
+                doc(slug, text),
+                true,
+                "property".to_owned(),
+            );
+        }
+        index.finish();
+
+        let search = |query_string: &str, overrides: &str| -> Vec<String> {
+            let mut ranking = RankingConfig::default();
+            ranking.apply_overrides(overrides).unwrap();
+            let options = SearchOptions {
+                ranking: Some(ranking),
+                ..SearchOptions::default()
+            };
+            let results = index.search(&Query::new(query_string, &[], &[]), &options);
+            results.results.iter().map(|r| r.document.slug.to_owned()).collect()
+        };
+
+        assert_eq!(search("fox", ""), vec!["fox", "foxglove"]);
+        assert_eq!(search("fox", "prefix_expansion:none"), vec!["fox"]);
+        assert_eq!(search("fox", "prefix_min_length:4"), vec!["fox"]);
+        assert_eq!(search("fox", "prefix_max_expansions:0"), vec!["fox"]);
+        assert_eq!(search("fox red", "prefix_expansion:last"), vec!["fox"]);
+        assert_eq!(search("red fox", "prefix_expansion:last"), vec!["fox", "foxglove"]);
+        assert_eq!(search("red fox ", "prefix_expansion:last"), vec!["fox"]);
+
+        // Required terms are expanded the same way
+        assert_eq!(search("+fox", ""), vec!["fox", "foxglove"]);
+        assert_eq!(search("+fox", "prefix_expansion:none"), vec!["fox"]);
+        assert_eq!(search("+fox", "prefix_min_length:4"), vec!["fox"]);
+        assert_eq!(search("+fox", "prefix_max_expansions:0"), vec!["fox"]);
+    }
+
+    #[test]
+    fn test_suggest() {
+        let mut index = FTSIndex::new(
+            vec![Field::new("text", 1.0), Field::new("title", 10.0)],
+            RankingConfig::default(),
+        );
+        for &(slug, title, text, property) in &[
+            ("fox", "Foxes", "The fox is foxing about.", "animals"),
+            ("foxglove", "Foxglove", "Foxglove is a flowering plant.", "plants"),
+            ("flowers", "Flowers", "Foxes flower rarely.", "plants"),
+        ] {
+            index.add(
+                ManifestDocument {
+                    title: title.to_owned(),
+                    ..doc(slug, text)
+                },
+                true,
+                property.to_owned(),
+            );
+        }
+        index.finish();
+
+        // Completions are unstemmed, and prefer words in more documents and weightier fields
+        let suggestions = index.suggest("red fox", &[], 10);
+        assert_eq!(suggestions.completions, vec!["red foxglove", "red foxes", "red fox", "red foxing"]);
+        assert_eq!(index.suggest("red fox", &[], 1).completions, vec!["red foxglove"]);
+        assert_eq!(index.suggest("foxgl", &[], 10).completions, vec!["foxglove"]);
+        assert_eq!(index.suggest("flowe", &[], 10).completions, vec!["flower", "flowering", "flowers"]);
+
+        let titles: Vec<_> = suggestions.titles.iter().map(|doc| doc.slug.as_str()).collect();
+        assert!(titles.is_empty());
+        let titles: Vec<_> = index
+            .suggest("Fox", &["plants"], 10)
+            .titles
+            .iter()
+            .map(|doc| doc.slug.as_str())
+            .collect();
+        assert_eq!(titles, vec!["foxglove"]);
+
+        assert_eq!(index.suggest("fox", &["plants"], 10).completions, vec!["foxglove", "foxes"]);
+        assert!(index.suggest("f", &[], 10).completions.is_empty());
+        assert!(index.suggest("fox ", &[], 10).completions.is_empty());
+    }
+
+    #[test]
+    fn test_link_analysis() {
+        let mut index = FTSIndex::new(
+            vec![Field::new("text", 1.0), Field::new("title", 10.0)],
+            RankingConfig::default(),
+        );
+        for &(slug, ref links) in &[
+            ("Fox", vec!["Red_fox"]),
+            ("Arctic_fox", vec!["Red_fox", "Fox"]),
+            ("Red_fox", vec![]),
+        ] {
+            index.add(
+                ManifestDocument {
+                    title: slug.replace('_', " "),
+                    links: links
+                        .iter()
+                        .map(|link| format!("https://en.wikipedia.org/wiki/{}", link))
+                        .collect(),
+                    url: format!("https://en.wikipedia.org/wiki/{}", slug),
+                    ..doc(slug, "Foxes are small-to-medium-sized, omnivorous mammals.")
+                },
+                true,
+                "property".to_owned(),
+            );
+        }
+        index.finish();
+
+        // The most linked-to document has the highest PageRank
+        assert!(index.pagerank[2] > index.pagerank[0]);
+        assert!(index.pagerank[0] > index.pagerank[1]);
+        assert!((index.pagerank.iter().sum::<f32>() - 1.0).abs() < 1e-4);
+
+        let query = Query::new("fox", &[], &[]);
+        let search = |link_analysis| {
+            let options = SearchOptions {
+                explain: true,
+                ranking: Some(RankingConfig {
+                    link_analysis,
+                    ..RankingConfig::default()
+                }),
+                ..SearchOptions::default()
+            };
+
+            index
+                .search(&query, &options)
+                .results
+                .into_iter()
+                .map(|r| (r.document._id, r.explanation.unwrap().authority))
+                .collect::<Vec<_>>()
+        };
+
+        let pagerank = search(LinkAnalysis::PageRank);
+        assert_eq!(pagerank[0].0, DocID(2));
+        for &(id, authority) in &pagerank {
+            assert_eq!(authority, index.pagerank[id.usize()]);
+        }
+
+        assert_eq!(search(LinkAnalysis::Hits)[0].0, DocID(2));
+        assert!(search(LinkAnalysis::None).iter().all(|&(_, a)| a == 0.0));
+    }
+
+    #[test]
+    fn test_cmp_scores() {
+        let mut scores = [1.0, f32::NAN, 3.0, 2.0];
+        scores.sort_by(|a, b| cmp_scores(*b, *a));
+        assert_eq!(&scores[..3], &[3.0, 2.0, 1.0]);
+        assert!(scores[3].is_nan());
     }
 }
@@ -1,3 +1,4 @@
+extern crate bincode;
 extern crate brotli2;
 extern crate futures;
 extern crate futures_cpupool;
@@ -23,20 +24,32 @@ extern crate serde_json;
 extern crate simple_logging;
 extern crate smallvec;
 extern crate time;
+extern crate toml;
 extern crate unicase;
 extern crate walkdir;
 
+mod config;
+mod corpus;
+mod eval;
 mod fts;
 mod manifest;
+mod pinned;
 mod porter2;
 mod protocol;
 mod query;
 mod queryst;
+mod refresh;
+mod scorer;
+mod snapshot;
+mod snippet;
+mod spelling;
 mod stemmer;
 mod trie;
 
 use brotli2::read::BrotliEncoder;
-use fts::FTSIndex;
+use config::{Command, Config, FieldConfig, RankingConfig};
+use corpus::Corpus;
+use fts::{FTSIndex, Filter, SearchOptions};
 use futures::future::Future;
 use futures_cpupool::CpuPool;
 use hyper::header::{self, HttpDate, IfModifiedSince};
@@ -44,16 +57,23 @@ use hyper::server::{Http, NewService, Request, Response, Service};
 use hyper::{Method, StatusCode};
 use manifest::ManifestLoader;
 use percent_encoding::percent_decode;
+use pinned::PinnedResults;
 use query::Query;
 use queryst::parse_query;
-use std::collections::HashMap;
+use refresh::{JobState, RefreshStatus};
+use std::collections::{BTreeSet, HashMap};
 use std::io::Read;
-use std::sync::{Arc, RwLock};
-use std::time::SystemTime;
-use std::{env, mem, process};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+use std::{cmp, env, mem, process};
 use unicase::Ascii;
 
+/// The longest search or suggestion query, in bytes.
 const MAXIMUM_QUERY_LENGTH: usize = 100;
+/// The longest value of any other query string parameter, in bytes.
+const MAXIMUM_PARAMETER_LENGTH: usize = 1000;
+const DEFAULT_SUGGESTIONS: usize = 10;
 
 fn timespec_from(st: &SystemTime) -> time::Timespec {
     if let Ok(dur_since_epoch) = st.duration_since(std::time::UNIX_EPOCH) {
@@ -94,16 +114,20 @@ fn compress(response: Response, req: &Request, content: String) -> Response {
     response.with_body(content)
 }
 
-fn default_fields() -> Vec<fts::Field> {
-    vec![
-        fts::Field::new("text", 1.0),
-        fts::Field::new("headings", 5.0),
-        fts::Field::new("title", 10.0),
-        fts::Field::new("tags", 75.0),
-    ]
+/// The parameters of a `/search` or `/suggest` request.
+struct QueryParameters<'a> {
+    params: HashMap<&'a str, &'a str>,
+    q: &'a str,
+    search_properties: Vec<&'a str>,
 }
 
-fn handle_search(marian: &Marian, request: &Request) -> Response {
+/// Decode and check the query string of a `/search` or `/suggest` request, and
+/// hand its parameters to `handler`.
+fn handle_query(
+    marian: &Marian,
+    request: &Request,
+    handler: fn(&Marian, &Request, &QueryParameters) -> Response,
+) -> Response {
     let query = match request.query() {
         Some(fq) => fq,
         None => {
@@ -118,18 +142,53 @@ fn handle_search(marian: &Marian, request: &Request) -> Response {
         }
     };
 
-    if query.len() > MAXIMUM_QUERY_LENGTH {
-        return Response::new().with_status(StatusCode::BadRequest);
-    }
-
-    let query = parse_query(query.as_ref());
-    let search_query = match query.get("q") {
-        Some(s) => s,
+    let params = parse_query(query.as_ref());
+    let q = match params.get("q") {
+        Some(s) => *s,
         None => {
             return Response::new().with_status(StatusCode::BadRequest);
         }
     };
 
+    if q.len() > MAXIMUM_QUERY_LENGTH
+        || params.values().any(|value| value.len() > MAXIMUM_PARAMETER_LENGTH)
+    {
+        return Response::new().with_status(StatusCode::BadRequest);
+    }
+
+    let search_properties: Vec<_> = match params.get("searchProperty") {
+        Some(s) => s.split(',').collect(),
+        None => vec![],
+    };
+
+    handler(
+        marian,
+        request,
+        &QueryParameters {
+            params,
+            q,
+            search_properties,
+        },
+    )
+}
+
+/// An empty response with the headers shared by the JSON query endpoints.
+fn json_response() -> Response {
+    Response::new()
+        .with_header(header::ContentType(mime::APPLICATION_JSON))
+        .with_header(header::Vary::Items(vec![Ascii::new(
+            "Accept-Encoding".to_owned(),
+        )]))
+        .with_header(header::CacheControl(vec![
+            header::CacheDirective::Public,
+            header::CacheDirective::MaxAge(120),
+            header::CacheDirective::MustRevalidate,
+        ]))
+        .with_header(header::AccessControlAllowOrigin::Any)
+}
+
+fn handle_search(marian: &Marian, request: &Request, parameters: &QueryParameters) -> Response {
+    let query = &parameters.params;
     let txn = marian.index.read().unwrap();
 
     if let Some(header) = request.headers().get::<IfModifiedSince>() {
@@ -144,83 +203,272 @@ fn handle_search(marian: &Marian, request: &Request) -> Response {
         }
     }
 
-    let search_properties: Vec<_> = match query.get("searchProperty") {
-        Some(s) => s.split(',').collect(),
-        None => vec![],
-    };
     let finished_time = std::time::UNIX_EPOCH + std::time::Duration::from_secs(0);
-    let response = Response::new()
-        .with_header(header::LastModified(HttpDate::from(finished_time)))
-        .with_header(header::ContentType(mime::APPLICATION_JSON))
-        .with_header(header::Vary::Items(vec![Ascii::new(
-            "Accept-Encoding".to_owned(),
-        )]))
-        .with_header(header::CacheControl(vec![
-            header::CacheDirective::Public,
-            header::CacheDirective::MaxAge(120),
-            header::CacheDirective::MustRevalidate,
-        ]))
-        .with_header(header::AccessControlAllowOrigin::Any);
+    let response =
+        json_response().with_header(header::LastModified(HttpDate::from(finished_time)));
+
+    let parsed_query = Query::new(
+        parameters.q,
+        &parameters.search_properties,
+        &txn.field_names(),
+    );
+
+    let offset = match query.get("offset").map(|s| s.parse()) {
+        Some(Ok(n)) => n,
+        Some(Err(_)) => return Response::new().with_status(StatusCode::BadRequest),
+        None => 0,
+    };
+
+    let limit = match query.get("limit").map(|s| s.parse()) {
+        Some(Ok(n)) => cmp::min(n, marian.max_results),
+        Some(Err(_)) => return Response::new().with_status(StatusCode::BadRequest),
+        None => marian.max_results,
+    };
 
-    let parsed_query = Query::new(search_query, &search_properties);
+    // Allow experimenting with ranking parameters without a restart
+    let ranking = if query.contains_key("ranking") || query.contains_key("scorer") {
+        let mut ranking = txn.ranking.clone();
+        if let Some(overrides) = query.get("ranking") {
+            if ranking.apply_overrides(overrides).is_err() {
+                return Response::new().with_status(StatusCode::BadRequest);
+            }
+        }
+
+        if let Some(scorer) = query.get("scorer") {
+            ranking.scorer = match scorer.parse() {
+                Ok(scorer) => scorer,
+                Err(_) => return Response::new().with_status(StatusCode::BadRequest),
+            };
+        }
+
+        Some(ranking)
+    } else {
+        None
+    };
+
+    let filter = match query.get("filter").map(|s| s.parse()) {
+        Some(Ok(filter)) => filter,
+        Some(Err(_)) => return Response::new().with_status(StatusCode::BadRequest),
+        None => Filter::default(),
+    };
+
+    let include_snippets = query.get("snippets") == Some(&"true");
+    let options = SearchOptions {
+        offset,
+        limit,
+        explain: query.get("explain") == Some(&"true"),
+        facets: query.get("facets") == Some(&"true"),
+        filter,
+        ranking,
+    };
 
-    let results: Vec<serde_json::Value> = txn
-        .search(&parsed_query)
+    let search_results = txn.search(&parsed_query, &options);
+    let results: Vec<serde_json::Value> = search_results
+        .results
         .iter()
-        .map(|doc| {
-            json![{
+        .map(|result| {
+            let doc = result.document;
+            let mut value = json![{
                     "title": doc.title,
                     "preview": doc.preview,
                     "url": &doc.url
-                }]
+                }];
+
+            if !doc.stored.is_empty() {
+                value["fields"] = json!(doc.stored);
+            }
+
+            if include_snippets {
+                // Snip the field with the most hits, preferring the longest
+                let mut matches = BTreeSet::new();
+                let mut best: Option<(&str, Vec<snippet::Hit>)> = None;
+                for field in &marian.fields {
+                    let text = match doc.field(&field.name) {
+                        Some(text) => text,
+                        None => continue,
+                    };
+
+                    let hits = txn.field_hits(doc, &field.name, text, &result.terms);
+                    matches.extend(snippet::matched_words(text, &hits));
+                    let is_better = match best {
+                        Some((best_text, ref best_hits)) => {
+                            (hits.len(), text.len()) > (best_hits.len(), best_text.len())
+                        }
+                        None => !hits.is_empty(),
+                    };
+
+                    if is_better {
+                        best = Some((text, hits));
+                    }
+                }
+
+                value["matches"] = json!(matches);
+                value["snippet"] = json!(best.and_then(|(text, hits)| {
+                    snippet::make_snippet(text, &hits)
+                }));
+            }
+
+            if result.pinned {
+                value["pinned"] = json!(true);
+            }
+
+            if let Some(ref explanation) = result.explanation {
+                value["explain"] = json!(explanation);
+            }
+
+            value
         })
         .collect();
 
-    let spelling_corrections: HashMap<String, String> = hashmap![];
-    let results = json![{ "results": results, "spellingCorrections": spelling_corrections }];
+    let spelling_corrections = txn.spelling_corrections(&parsed_query);
+    let mut results = json![{
+        "results": results,
+        "total": search_results.total,
+        "spellingCorrections": spelling_corrections
+    }];
+
+    if !search_results.fuzzy_matches.is_empty() {
+        results["fuzzyMatches"] = json!(search_results.fuzzy_matches);
+    }
+
+    if options.facets {
+        results["facets"] = json!(search_results.facets);
+    }
 
     let serialized = serde_json::to_string(&results).unwrap();
     compress(response, request, serialized)
 }
 
-fn handle_refresh(marian: &Marian) -> Result<(), String> {
-    let manifest_loader = &*marian.manifest_loader;
-
-    let mut manifests = manifest_loader.load()?;
-    let mut new_index = FTSIndex::new(default_fields());
-
-    for manifest in manifests.drain(..) {
-        let mut manifest = match manifest {
-            Ok(manifest) => manifest,
-            Err(err) => {
-                new_index.manifest_errors.insert(err.search_property, err.message);
-                continue;
-            },
-        };
+fn handle_suggest(marian: &Marian, request: &Request, parameters: &QueryParameters) -> Response {
+    let query = &parameters.params;
+    let limit = match query.get("limit").map(|s| s.parse()) {
+        Some(Ok(n)) => cmp::min(n, marian.max_results),
+        Some(Err(_)) => return Response::new().with_status(StatusCode::BadRequest),
+        None => cmp::min(DEFAULT_SUGGESTIONS, marian.max_results),
+    };
 
-        while manifest.body.url.ends_with('/') {
-            manifest.body.url.pop();
+    let response = json_response();
+    let txn = marian.index.read().unwrap();
+    let suggestions = txn.suggest(parameters.q, &parameters.search_properties, limit);
+    let titles: Vec<serde_json::Value> = suggestions
+        .titles
+        .iter()
+        .map(|doc| {
+            json![{
+                "title": doc.title,
+                "url": &doc.url
+            }]
+        })
+        .collect();
+
+    let results = json![{
+        "completions": suggestions.completions,
+        "titles": titles
+    }];
+
+    let serialized = serde_json::to_string(&results).unwrap();
+    compress(response, request, serialized)
+}
+
+/// Write a snapshot of a freshly built index, if snapshots are enabled. Failure
+/// is logged but not fatal: we can always rebuild.
+fn save_snapshot(marian: &Marian, index: &FTSIndex) {
+    if let Some(ref path) = marian.snapshot_path {
+        if let Err(msg) = snapshot::save(path, index) {
+            error!("Error saving snapshot: {}", msg);
         }
+    }
+}
+
+/// Read the pinned results file, if one is configured, into `index`. A broken
+/// file shouldn't stop the index from being rebuilt, so on failure the error
+/// is reported alongside any manifest errors and false is returned, leaving
+/// the caller to decide which rules to keep.
+fn load_pinned(marian: &Marian, index: &mut FTSIndex) -> bool {
+    let path = match marian.pinned_path {
+        Some(ref path) => path,
+        None => return true,
+    };
 
-        for alias in manifest.body.aliases.drain(..) {
-            new_index.alias_search_property(alias.to_owned(), manifest.search_property.to_owned());
+    match PinnedResults::from_file(path) {
+        Ok(pinned) => {
+            index.pinned = pinned;
+            true
+        }
+        Err(msg) => {
+            error!("{}", msg);
+            index
+                .manifest_errors
+                .insert(path.display().to_string(), msg);
+            false
         }
+    }
+}
 
-        let include_in_global_search = manifest.body.include_in_global_search;
+/// Reload any changed manifests and pinned results, and rebuild the index.
+/// Holding the corpus lock guarantees that only one refresh runs at a time.
+fn refresh_corpus(marian: &Marian, corpus: &mut Corpus) -> Result<(), String> {
+    let result = marian
+        .manifest_loader
+        .load(&corpus.last_modified())
+        .map(|manifests| {
+            corpus.update(manifests, &marian.index_fields());
+            let mut new_index = corpus.build_index(marian.index_fields(), marian.ranking.clone());
+            save_snapshot(marian, &new_index);
+            let loaded_pinned = load_pinned(marian, &mut new_index);
+
+            let mut txn = marian.index.write().unwrap();
+            if !loaded_pinned {
+                // Keep pinning results as before until the file is fixed
+                mem::swap(&mut new_index.pinned, &mut txn.pinned);
+            }
+
+            *txn = new_index;
+        });
+
+    marian.refresh_status.lock().unwrap().record(&result);
+    result
+}
 
-        for mut doc in manifest.body.documents.drain(..) {
-            while doc.slug.ends_with('/') {
-                doc.slug.pop();
+fn handle_refresh(marian: &Marian) -> Result<(), String> {
+    let mut corpus = marian.corpus.lock().unwrap();
+    refresh_corpus(marian, &mut corpus)
+}
+
+/// Initialize the index at startup, reusing a snapshot if one exists and every
+/// manifest is unchanged since it was written. Otherwise fall back to a full rebuild.
+fn handle_startup(marian: &Marian) -> Result<(), String> {
+    let mut index = match marian.snapshot_path {
+        Some(ref path) if path.exists() => match snapshot::load(path) {
+            Ok(index) => index,
+            Err(msg) => {
+                warn!("Ignoring snapshot: {}", msg);
+                return handle_refresh(marian);
             }
-            doc.url = format!("{}/{}", manifest.body.url, doc.slug);
-            new_index.add(doc, include_in_global_search, manifest.search_property.to_owned());
-        }
+        },
+        _ => return handle_refresh(marian),
+    };
+
+    let manifests = marian.manifest_loader.load(&index.last_modified)?;
+    let is_fresh = snapshot::is_fresh(&index.last_modified, &manifests)
+        && index.has_fields(&marian.index_fields());
+    if !is_fresh {
+        info!("Snapshot is stale; rebuilding index");
+        return handle_refresh(marian);
     }
 
-    new_index.finish();
+    info!("Loaded index from snapshot");
+    index.manifest_errors = manifests
+        .into_iter()
+        .filter_map(|manifest| manifest.err())
+        .map(|err| (err.search_property, err.message))
+        .collect();
+    index.rebuilt.clear();
+    index.ranking = marian.ranking.clone();
+    load_pinned(marian, &mut index);
 
     let mut txn = marian.index.write().unwrap();
-    mem::replace(&mut *txn, new_index);
+    *txn = index;
     Ok(())
 }
 
@@ -228,21 +476,39 @@ pub struct Marian {
     index: RwLock<FTSIndex>,
     workers: CpuPool,
     manifest_loader: Box<ManifestLoader>,
+    corpus: Mutex<Corpus>,
+    refresh_status: Mutex<RefreshStatus>,
+    snapshot_path: Option<PathBuf>,
+    pinned_path: Option<PathBuf>,
+    max_results: usize,
+    fields: Vec<FieldConfig>,
+    ranking: RankingConfig,
 }
 
 impl Marian {
-    fn new(manifest_loader: Box<ManifestLoader>) -> Result<Self, String> {
-        let index = FTSIndex::new(default_fields());
+    fn new(manifest_loader: Box<ManifestLoader>, config: &Config) -> Result<Self, String> {
+        let index = FTSIndex::new(config.index_fields(), config.ranking.clone());
         let service = Self {
             index: RwLock::new(index),
-            workers: CpuPool::new(num_cpus::get()),
+            workers: CpuPool::new(config.workers),
             manifest_loader,
+            corpus: Mutex::new(Corpus::new()),
+            refresh_status: Mutex::new(RefreshStatus::new()),
+            snapshot_path: config.snapshot.to_owned(),
+            pinned_path: config.pinned.to_owned(),
+            max_results: config.max_results,
+            fields: config.fields.to_owned(),
+            ranking: config.ranking.clone(),
         };
 
-        handle_refresh(&service)?;
+        handle_startup(&service)?;
 
         Ok(service)
     }
+
+    fn index_fields(&self) -> Vec<fts::Field> {
+        self.fields.iter().map(fts::Field::from_config).collect()
+    }
 }
 
 struct MarianServiceFactory {
@@ -277,6 +543,18 @@ impl MarianService {
             )]))
             .with_body(serialized)
     }
+
+    fn refresh_job(&self, id: u64) -> Response {
+        let status = self.ctx.refresh_status.lock().unwrap();
+        let state = match status.job_state(id) {
+            Some(state) => state,
+            None => return Response::new().with_status(StatusCode::NotFound),
+        };
+
+        Response::new()
+            .with_header(header::ContentType(mime::APPLICATION_JSON))
+            .with_body(protocol::create_refresh_job_string(id, Some(state)))
+    }
 }
 
 impl Service for MarianService {
@@ -291,24 +569,49 @@ impl Service for MarianService {
                 (&Method::Get, "/search") => {
                     let marian = Arc::clone(&self.ctx);
                     return Box::new(self.ctx.workers.spawn_fn(move || {
-                        Box::new(futures::future::ok(handle_search(&marian, &req)))
+                        Box::new(futures::future::ok(handle_query(&marian, &req, handle_search)))
                     }));
                 }
-                (&Method::Get, "/status") => self.status(),
-                (&Method::Post, "/refresh") => {
+                (&Method::Get, "/suggest") => {
                     let marian = Arc::clone(&self.ctx);
                     return Box::new(self.ctx.workers.spawn_fn(move || {
-                        let response = match handle_refresh(&marian) {
-                            Ok(_) => Response::new(),
-                            Err(msg) => {
-                                error!("Error loading manifests: {}", msg);
-                                Response::new().with_status(StatusCode::InternalServerError)
-                            }
+                        Box::new(futures::future::ok(handle_query(&marian, &req, handle_suggest)))
+                    }));
+                }
+                (&Method::Get, "/status") => self.status(),
+                (&Method::Post, "/refresh") => {
+                    let wants_async = req.headers()
+                        .get::<header::Prefer>()
+                        .map(|prefer| prefer.contains(&header::Preference::RespondAsync))
+                        .unwrap_or(false);
+                    let id = refresh::start(&self.ctx);
+                    if wants_async {
+                        return Box::new(futures::future::ok(
+                            self.refresh_job(id)
+                                .with_status(StatusCode::Accepted)
+                                .with_header(header::Location::new(format!("/refresh/{}", id))),
+                        ));
+                    }
+
+                    return Box::new(refresh::wait(&self.ctx, id).then(move |state| {
+                        let state = state.ok();
+                        let response = match state {
+                            Some(JobState::Succeeded) => Response::new(),
+                            _ => Response::new().with_status(StatusCode::InternalServerError),
                         };
-                        Box::new(futures::future::ok(response))
+                        let body = protocol::create_refresh_job_string(id, state.as_ref());
+                        Ok(response
+                            .with_header(header::ContentType(mime::APPLICATION_JSON))
+                            .with_body(body))
                     }));
                 }
-                (_, "/search") | (_, "/status") | (_, "/refresh") => {
+                (&Method::Get, path) if path.starts_with("/refresh/") => {
+                    match path["/refresh/".len()..].parse() {
+                        Ok(id) => self.refresh_job(id),
+                        Err(_) => Response::new().with_status(StatusCode::NotFound),
+                    }
+                }
+                (_, "/search") | (_, "/suggest") | (_, "/status") | (_, "/refresh") => {
                     Response::new().with_status(StatusCode::MethodNotAllowed)
                 }
                 _ => Response::new().with_status(StatusCode::NotFound),
@@ -319,19 +622,32 @@ impl Service for MarianService {
 }
 
 fn usage(exit_code: i32) -> ! {
-    eprintln!("Usage: marian-rust <dir|bucket>:<...>");
+    eprintln!("{}", config::USAGE);
     process::exit(exit_code);
 }
 
 fn main() {
-    simple_logging::log_to_stderr(log::LevelFilter::Info);
+    let config = match config::parse_args(env::args().skip(1)) {
+        Ok(Command::Serve(config)) => config,
+        Ok(Command::Eval(options)) => {
+            simple_logging::log_to_stderr(options.config.log_level().unwrap());
+            if let Err(msg) = eval::run(&options) {
+                eprintln!("{}", msg);
+                process::exit(1);
+            }
 
-    let manifest_source = match env::args().nth(1) {
-        Some(s) => s,
-        None => usage(1),
+            process::exit(0)
+        }
+        Ok(Command::Help) => usage(0),
+        Err(msg) => {
+            eprintln!("{}\n", msg);
+            usage(1)
+        }
     };
 
-    let manifest_source = match manifest::parse_manifest_source(&manifest_source) {
+    simple_logging::log_to_stderr(config.log_level().unwrap());
+
+    let manifest_source = match manifest::parse_manifest_sources(&config.sources) {
         Ok(s) => s,
         Err(msg) => {
             error!("{}", msg);
@@ -339,7 +655,16 @@ fn main() {
         }
     };
 
-    let marian = match Marian::new(manifest_source) {
+    // Catch a bad address before spending time building the index
+    let addr = match config.listen.parse() {
+        Ok(addr) => addr,
+        Err(_) => {
+            error!("Invalid listen address: {}", config.listen);
+            process::exit(1)
+        }
+    };
+
+    let marian = match Marian::new(manifest_source, &config) {
         Ok(m) => m,
         Err(msg) => {
             error!("{}", msg);
@@ -347,13 +672,23 @@ fn main() {
         }
     };
 
-    let factory = MarianServiceFactory {
-        marian: Arc::new(marian),
+    let marian = Arc::new(marian);
+    refresh::spawn_scheduler(
+        Arc::clone(&marian),
+        config.refresh_interval.map(Duration::from_secs),
+        config.watch,
+    );
+
+    let factory = MarianServiceFactory { marian };
+
+    let server = match Http::new().bind(&addr, factory) {
+        Ok(server) => server,
+        Err(err) => {
+            error!("Failed to listen on {}: {}", config.listen, err);
+            process::exit(1)
+        }
     };
 
-    let interface = "127.0.0.1:3000";
-    info!("Listening on http://{}", interface);
-    let addr = interface.parse().unwrap();
-    let server = Http::new().bind(&addr, factory).unwrap();
+    info!("Listening on http://{}", addr);
     server.run().unwrap();
 }
@@ -4,8 +4,11 @@ use rusoto_core;
 use rusoto_s3::{self, S3};
 use serde_json;
 use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::error::Error;
 use std::fs::File;
+use std::hash::{Hash, Hasher};
 use std::io::prelude::*;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
@@ -24,6 +27,19 @@ pub struct ManifestDocument {
 
     #[serde(skip)]
     pub url: String,
+
+    /// Scales the document's score, to promote important pages or demote obscure ones.
+    #[serde(default)]
+    pub boost: Option<f32>,
+
+    /// Keyword attributes that searches may be filtered on, such as a version
+    /// or product. Values may be strings, numbers, booleans, or arrays of them.
+    #[serde(default)]
+    pub metadata: HashMap<String, serde_json::Value>,
+
+    /// Any other fields, which are indexed if configured to be.
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
 }
 
 impl ManifestDocument {
@@ -33,9 +49,51 @@ impl ManifestDocument {
             "text" => Some(Cow::Borrowed(&self.text)),
             "headings" => Some(Cow::Owned(self.headings.join(" "))),
             "tags" => Some(Cow::Borrowed(&self.tags)),
-            _ => None,
+            _ => match self.extra.get(key) {
+                Some(&serde_json::Value::String(ref s)) => Some(Cow::Borrowed(s)),
+                Some(&serde_json::Value::Array(ref values)) => Some(Cow::Owned(
+                    values
+                        .iter()
+                        .filter_map(|value| value.as_str())
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                )),
+                _ => None,
+            },
         }
     }
+
+    /// Return the lowercased keyword values of each metadata attribute.
+    pub fn keywords(&self) -> BTreeMap<String, BTreeSet<String>> {
+        fn add_values(value: &serde_json::Value, values: &mut BTreeSet<String>) {
+            match *value {
+                serde_json::Value::String(ref s) => {
+                    values.insert(s.to_lowercase());
+                }
+                serde_json::Value::Number(ref n) => {
+                    values.insert(n.to_string());
+                }
+                serde_json::Value::Bool(b) => {
+                    values.insert(b.to_string());
+                }
+                serde_json::Value::Array(ref array) => for value in array {
+                    add_values(value, values);
+                },
+                _ => (),
+            }
+        }
+
+        let mut keywords = BTreeMap::new();
+        for (key, value) in &self.metadata {
+            let mut values = BTreeSet::new();
+            add_values(value, &mut values);
+            if !values.is_empty() {
+                keywords.insert(key.to_owned(), values);
+            }
+        }
+
+        keywords
+    }
 }
 
 #[derive(Deserialize)]
@@ -46,12 +104,18 @@ pub struct ManifestData {
     #[serde(default)]
     pub aliases: Vec<String>,
 
+    /// Scales the score of every document in the manifest.
+    #[serde(default)]
+    pub boost: Option<f32>,
+
     pub documents: Vec<ManifestDocument>,
     pub url: String,
 }
 
 pub struct Manifest {
-    pub body: ManifestData,
+    /// None if the manifest was skipped because it is unchanged since the
+    /// modification time the caller already knew about.
+    pub body: Option<ManifestData>,
     pub last_modified: SystemTime,
     pub search_property: String,
 }
@@ -78,8 +142,19 @@ impl ManifestError {
 }
 
 pub trait ManifestLoader: Send + Sync {
-    fn load(&self) -> Result<Vec<Result<Manifest, ManifestError>>, String>;
+    /// Load all manifests. Manifests whose modification time matches the one
+    /// recorded for their search property in `known` are not read.
+    fn load(
+        &self,
+        known: &HashMap<String, SystemTime>,
+    ) -> Result<Vec<Result<Manifest, ManifestError>>, String>;
     fn parts(&self) -> Vec<String>;
+
+    /// Return a value that changes whenever a manifest may have changed, or
+    /// None if this source cannot be cheaply watched for changes.
+    fn fingerprint(&self) -> Option<u64> {
+        None
+    }
 }
 
 pub struct FileManifestLoader {
@@ -93,10 +168,15 @@ impl FileManifestLoader {
 }
 
 impl ManifestLoader for FileManifestLoader {
-    fn load(&self) -> Result<Vec<Result<Manifest, ManifestError>>, String> {
+    fn load(
+        &self,
+        known: &HashMap<String, SystemTime>,
+    ) -> Result<Vec<Result<Manifest, ManifestError>>, String> {
         let mut manifests = vec![];
 
-        for entry in WalkDir::new(&self.path) {
+        // Sort so that if two manifests share a stem, the same one always wins
+        let walker = WalkDir::new(&self.path).sort_by(|a, b| a.file_name().cmp(b.file_name()));
+        for entry in walker {
             let entry = entry.or_else(|_| {
                 Err(format!(
                     "Error scanning input directory: {}",
@@ -119,6 +199,21 @@ impl ManifestLoader for FileManifestLoader {
                     &entry.path().display()
                 ))
             })?;
+
+            let search_property = match entry.path().file_stem() {
+                Some(stem) => stem.to_string_lossy().to_string(),
+                None => String::new(),
+            };
+
+            if known.get(&search_property) == Some(&mtime) {
+                manifests.push(Ok(Manifest {
+                    body: None,
+                    last_modified: mtime,
+                    search_property,
+                }));
+                continue;
+            }
+
             let mut file = File::open(&entry.path()).or_else(|_| {
                 Err(format!(
                     "Failed to open manifest file: {}",
@@ -140,13 +235,8 @@ impl ManifestLoader for FileManifestLoader {
                 ))
             })?;
 
-            let search_property = match entry.path().file_stem() {
-                Some(stem) => stem.to_string_lossy().to_string(),
-                None => String::new(),
-            };
-
             manifests.push(Ok(Manifest {
-                body,
+                body: Some(body),
                 last_modified: mtime,
                 search_property,
             }));
@@ -158,6 +248,19 @@ impl ManifestLoader for FileManifestLoader {
     fn parts(&self) -> Vec<String> {
         return vec![self.path.to_string_lossy().into_owned()];
     }
+
+    fn fingerprint(&self) -> Option<u64> {
+        let mut hasher = DefaultHasher::new();
+        for entry in WalkDir::new(&self.path).sort_by(|a, b| a.file_name().cmp(b.file_name())) {
+            let entry = entry.ok()?;
+            let metadata = entry.metadata().ok()?;
+            entry.path().hash(&mut hasher);
+            metadata.len().hash(&mut hasher);
+            metadata.modified().ok()?.hash(&mut hasher);
+        }
+
+        Some(hasher.finish())
+    }
 }
 
 pub struct S3ManifestLoader {
@@ -182,7 +285,10 @@ impl S3ManifestLoader {
 }
 
 impl ManifestLoader for S3ManifestLoader {
-    fn load(&self) -> Result<Vec<Result<Manifest, ManifestError>>, String> {
+    fn load(
+        &self,
+        known: &HashMap<String, SystemTime>,
+    ) -> Result<Vec<Result<Manifest, ManifestError>>, String> {
         let client = rusoto_s3::S3Client::simple(rusoto_core::region::Region::default());
         let mut request = rusoto_s3::ListObjectsV2Request::default();
         request.bucket = self.bucket.to_owned();
@@ -218,6 +324,21 @@ impl ManifestLoader for S3ManifestLoader {
                     stem.to_string_lossy().to_string()
                 };
 
+                let mtime = match object.last_modified {
+                    Some(s) => HttpDate::from_str(&s).ok(),
+                    _ => None,
+                }.map(|d| SystemTime::from(d));
+
+                if let Some(mtime) = mtime {
+                    if known.get(&search_property) == Some(&mtime) {
+                        return Ok(Manifest {
+                            body: None,
+                            last_modified: mtime,
+                            search_property,
+                        });
+                    }
+                }
+
                 let mut get_request = rusoto_s3::GetObjectRequest::default();
                 get_request.bucket = self.bucket.to_owned();
                 get_request.key = key;
@@ -238,15 +359,9 @@ impl ManifestLoader for S3ManifestLoader {
                 let body = serde_json::from_str(&body)
                     .map_err(|err| ManifestError::new_from_err(get_request.key.as_ref(), &err))?;
 
-                let mtime = match object.last_modified {
-                    Some(s) => HttpDate::from_str(&s).ok(),
-                    _ => None,
-                }.map(|d| SystemTime::from(d))
-                    .unwrap_or_else(|| SystemTime::now());
-
                 Ok(Manifest {
-                    body,
-                    last_modified: mtime,
+                    body: Some(body),
+                    last_modified: mtime.unwrap_or_else(|| SystemTime::now()),
                     search_property,
                 })
             })
@@ -260,6 +375,100 @@ impl ManifestLoader for S3ManifestLoader {
     }
 }
 
+/// Loads manifests from several sources and merges them. If more than one source
+/// provides the same search property, the source listed first takes precedence,
+/// and the conflict is reported as an error against that search property.
+pub struct CompositeManifestLoader {
+    loaders: Vec<(String, Box<ManifestLoader>)>,
+}
+
+impl CompositeManifestLoader {
+    pub fn new(loaders: Vec<(String, Box<ManifestLoader>)>) -> Self {
+        Self { loaders }
+    }
+}
+
+impl ManifestLoader for CompositeManifestLoader {
+    fn load(
+        &self,
+        known: &HashMap<String, SystemTime>,
+    ) -> Result<Vec<Result<Manifest, ManifestError>>, String> {
+        let mut manifests: Vec<Result<Manifest, ManifestError>> = vec![];
+        let mut providers: HashMap<String, Vec<&str>> = HashMap::new();
+
+        for &(ref source, ref loader) in &self.loaders {
+            let loaded = loader
+                .load(known)
+                .map_err(|msg| format!("Error loading {}: {}", source, msg))?;
+
+            for manifest in loaded {
+                let search_property = match manifest {
+                    Ok(ref m) => m.search_property.to_owned(),
+                    Err(ref err) => err.search_property.to_owned(),
+                };
+
+                let sources = providers.entry(search_property).or_insert_with(Vec::new);
+                sources.push(source);
+                if sources.len() == 1 {
+                    manifests.push(manifest);
+                }
+            }
+        }
+
+        let mut conflicts = vec![];
+        for manifest in &mut manifests {
+            let search_property = match *manifest {
+                Ok(ref m) => &m.search_property,
+                Err(ref err) => &err.search_property,
+            };
+
+            let sources = &providers[search_property];
+            if sources.len() < 2 {
+                continue;
+            }
+
+            let message = format!(
+                "Provided by multiple sources ({}); using {}",
+                sources.join(", "),
+                sources[0]
+            );
+
+            // Don't clobber the winning source's own error
+            match *manifest {
+                Ok(_) => conflicts.push(ManifestError::new(search_property.to_owned(), message)),
+                Err(ref mut err) => {
+                    err.message = format!("{}; {}", err.message, message);
+                }
+            }
+        }
+
+        manifests.extend(conflicts.drain(..).map(Err));
+        Ok(manifests)
+    }
+
+    fn parts(&self) -> Vec<String> {
+        self.loaders
+            .iter()
+            .flat_map(|&(_, ref loader)| loader.parts())
+            .collect()
+    }
+
+    fn fingerprint(&self) -> Option<u64> {
+        let fingerprints: Vec<_> = self
+            .loaders
+            .iter()
+            .map(|&(_, ref loader)| loader.fingerprint())
+            .collect();
+        if fingerprints.iter().all(|f| f.is_none()) {
+            return None;
+        }
+
+        let mut hasher = DefaultHasher::new();
+        fingerprints.hash(&mut hasher);
+        Some(hasher.finish())
+    }
+}
+
 pub fn parse_manifest_source(source: &str) -> Result<Box<ManifestLoader>, String> {
     if source.starts_with("dir:") {
         Ok(Box::new(FileManifestLoader::new(&source[4..])))
@@ -273,10 +482,62 @@ pub fn parse_manifest_source(source: &str) -> Result<Box<ManifestLoader>, String
     }
 }
 
+/// Parse one or more manifest sources into a single loader.
+pub fn parse_manifest_sources(sources: &[String]) -> Result<Box<ManifestLoader>, String> {
+    if sources.len() == 1 {
+        return parse_manifest_source(&sources[0]);
+    }
+
+    let mut loaders = vec![];
+    for source in sources {
+        loaders.push((source.to_owned(), parse_manifest_source(source)?));
+    }
+
+    Ok(Box::new(CompositeManifestLoader::new(loaders)))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    struct StaticManifestLoader {
+        search_properties: Vec<&'static str>,
+        url: &'static str,
+    }
+
+    impl ManifestLoader for StaticManifestLoader {
+        fn load(
+            &self,
+            _known: &HashMap<String, SystemTime>,
+        ) -> Result<Vec<Result<Manifest, ManifestError>>, String> {
+            Ok(self
+                .search_properties
+                .iter()
+                .map(|&search_property| {
+                    if search_property.starts_with("broken") {
+                        return Err(ManifestError::new(search_property, "Broken"));
+                    }
+
+                    Ok(Manifest {
+                        body: Some(ManifestData {
+                            include_in_global_search: true,
+                            aliases: vec![],
+                            boost: None,
+                            documents: vec![],
+                            url: self.url.to_owned(),
+                        }),
+                        last_modified: SystemTime::now(),
+                        search_property: search_property.to_owned(),
+                    })
+                })
+                .collect())
+        }
+
+        fn parts(&self) -> Vec<String> {
+            vec![self.url.to_owned()]
+        }
+    }
+
     #[test]
     fn test_parse_bucket() {
         assert_eq!(
@@ -291,4 +552,122 @@ mod tests {
     fn test_unknown_protocol() {
         assert!(parse_manifest_source("di:foobar").is_err());
     }
+
+    #[test]
+    fn test_composite() {
+        let loader = CompositeManifestLoader::new(vec![
+            (
+                "dir:a".to_owned(),
+                Box::new(StaticManifestLoader {
+                    search_properties: vec!["atlas", "broken-compass"],
+                    url: "a",
+                }),
+            ),
+            (
+                "dir:b".to_owned(),
+                Box::new(StaticManifestLoader {
+                    search_properties: vec!["bi-connector", "atlas", "broken-compass"],
+                    url: "b",
+                }),
+            ),
+        ]);
+
+        let mut loaded = HashMap::new();
+        let mut errors = HashMap::new();
+        for manifest in loader.load(&hashmap![]).unwrap() {
+            match manifest {
+                Ok(m) => {
+                    loaded.insert(m.search_property, m.body.unwrap().url);
+                }
+                Err(err) => {
+                    errors.insert(err.search_property, err.message);
+                }
+            }
+        }
+
+        // The first source listed takes precedence
+        assert_eq!(
+            loaded,
+            hashmap![
+                "atlas".to_owned() => "a".to_owned(),
+                "bi-connector".to_owned() => "b".to_owned(),
+            ]
+        );
+        assert_eq!(
+            errors,
+            hashmap![
+                "atlas".to_owned() =>
+                    "Provided by multiple sources (dir:a, dir:b); using dir:a".to_owned(),
+                "broken-compass".to_owned() =>
+                    "Broken; Provided by multiple sources (dir:a, dir:b); using dir:a".to_owned(),
+            ]
+        );
+        assert_eq!(loader.parts(), vec!["a".to_owned(), "b".to_owned()]);
+    }
+
+    #[test]
+    fn test_parse_sources() {
+        let sources = vec!["dir:a".to_owned(), "bucket:example/a/prefix".to_owned()];
+        assert_eq!(
+            parse_manifest_sources(&sources).unwrap().parts(),
+            vec!["a".to_owned(), "example".to_owned(), "a/prefix".to_owned()]
+        );
+        assert!(parse_manifest_sources(&["dir:a".to_owned(), "di:b".to_owned()]).is_err());
+    }
+
+    #[test]
+    fn test_skip_unchanged_files() {
+        let loader = FileManifestLoader::new("test/manifests");
+        let mut known = HashMap::new();
+        let mut order = vec![];
+        for manifest in loader.load(&known).unwrap() {
+            let manifest = manifest.ok().unwrap();
+            assert!(manifest.body.is_some());
+            order.push(manifest.search_property.to_owned());
+            known.insert(manifest.search_property, manifest.last_modified);
+        }
+
+        assert_eq!(known.len(), 2);
+        assert_eq!(order, vec!["atlas-master", "bi-connector-master"]);
+        known.remove("atlas-master");
+
+        for manifest in loader.load(&known).unwrap() {
+            let manifest = manifest.ok().unwrap();
+            assert_eq!(
+                manifest.body.is_some(),
+                manifest.search_property == "atlas-master"
+            );
+        }
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let loader = FileManifestLoader::new("test/manifests");
+        assert!(loader.fingerprint().is_some());
+        assert_eq!(loader.fingerprint(), loader.fingerprint());
+        assert_ne!(
+            loader.fingerprint(),
+            FileManifestLoader::new("test/manifests/atlas-master.json").fingerprint()
+        );
+        assert!(FileManifestLoader::new("test/nonexistent").fingerprint().is_none());
+    }
+
+    #[test]
+    fn test_extra_fields() {
+        let doc: ManifestDocument = serde_json::from_str(
+            r#"{
+                "slug": "fox", "title": "Fox", "tags": "", "headings": ["Range"],
+                "text": "", "preview": "", "links": [],
+                "summary": "A small omnivore",
+                "api_signatures": ["Fox.new()", "Fox.hunt(prey)", 3],
+                "rank": 3
+            }"#,
+        ).unwrap();
+
+        assert_eq!(doc.get("headings").unwrap(), "Range");
+        assert_eq!(doc.get("summary").unwrap(), "A small omnivore");
+        assert_eq!(doc.get("api_signatures").unwrap(), "Fox.new() Fox.hunt(prey)");
+        assert_eq!(doc.get("rank"), None);
+        assert_eq!(doc.get("missing"), None);
+    }
 }
@@ -0,0 +1,116 @@
+use serde_json;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+use stemmer::{is_stop_word, stem, tokenize};
+use toml;
+
+/// Reduce query terms to their sorted, deduplicated stems, so that queries
+/// differing only in case, punctuation, word forms, or word order are the same.
+pub fn normalize_terms<'a, I: IntoIterator<Item = &'a String>>(terms: I) -> String {
+    let mut stems: Vec<String> = terms
+        .into_iter()
+        .filter(|term| !is_stop_word(term))
+        .map(|term| stem(term))
+        .collect();
+    stems.sort();
+    stems.dedup();
+    stems.join(" ")
+}
+
+pub fn normalize(query: &str) -> String {
+    normalize_terms(&tokenize(query, false))
+}
+
+/// Curated results to show first for particular queries.
+#[derive(Default, Debug, PartialEq)]
+pub struct PinnedResults {
+    /// The URLs to pin, in order, for each normalized query.
+    rules: BTreeMap<String, Vec<String>>,
+}
+
+impl PinnedResults {
+    pub fn new(rules: BTreeMap<String, Vec<String>>) -> Result<Self, String> {
+        let mut normalized = BTreeMap::new();
+        for (query, urls) in rules {
+            let key = normalize(&query);
+            if key.is_empty() {
+                return Err(format!("Pinned query has no searchable terms: {}", query));
+            }
+
+            if normalized.insert(key, urls).is_some() {
+                return Err(format!("Pinned query is declared more than once: {}", query));
+            }
+        }
+
+        Ok(Self { rules: normalized })
+    }
+
+    /// Parse a file mapping queries to lists of URLs. Files ending in .json are
+    /// parsed as JSON; anything else is parsed as TOML.
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let mut file = File::open(path)
+            .map_err(|_| format!("Failed to open pinned results file: {}", path.display()))?;
+        let mut data = String::new();
+        file.read_to_string(&mut data)
+            .map_err(|_| format!("Failed to read pinned results file: {}", path.display()))?;
+
+        let is_json = path.extension().map(|ext| ext == "json").unwrap_or(false);
+        let parsed = if is_json {
+            serde_json::from_str(&data).map_err(|err| err.to_string())
+        } else {
+            toml::from_str(&data).map_err(|err| err.to_string())
+        };
+
+        parsed.and_then(Self::new).map_err(|msg| {
+            format!(
+                "Failed to parse pinned results file: {}\n{}",
+                path.display(),
+                msg
+            )
+        })
+    }
+
+    pub fn rules(&self) -> &BTreeMap<String, Vec<String>> {
+        &self.rules
+    }
+
+    /// The URLs pinned for a query with the given terms.
+    pub fn get<'a, I: IntoIterator<Item = &'a String>>(&self, terms: I) -> &[String] {
+        match self.rules.get(&normalize_terms(terms)) {
+            Some(urls) => urls,
+            None => &[],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pinned() {
+        let pinned = PinnedResults::new(btreemap!{
+            "Connection Strings".to_owned() => vec!["https://example.com/connect".to_owned()],
+        }).unwrap();
+        assert_eq!(
+            pinned.rules().keys().collect::<Vec<_>>(),
+            vec![&normalize("connection string")]
+        );
+
+        let get = |query: &str| pinned.get(&tokenize(query, false)).to_owned();
+        assert_eq!(get("connection string"), vec!["https://example.com/connect"]);
+        assert_eq!(get("the strings of connections"), vec!["https://example.com/connect"]);
+        assert!(get("connection").is_empty());
+        assert!(get("connection string uri").is_empty());
+
+        assert!(PinnedResults::new(btreemap!{"the".to_owned() => vec![]}).is_err());
+        assert!(
+            PinnedResults::new(btreemap!{
+                "connection string".to_owned() => vec![],
+                "connecting strings".to_owned() => vec![],
+            }).is_err()
+        );
+    }
+}
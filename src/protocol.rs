@@ -1,38 +1,93 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use serde_json;
 use time;
+use timespec_from;
+use refresh::JobState;
 use Marian;
 
 #[derive(Serialize, Debug)]
-struct LastSync {
+struct LastSync<'a> {
     finished: String,
+    rebuilt: Vec<&'a String>,
+}
+
+#[derive(Serialize, Debug)]
+struct Refresh<'a> {
+    #[serde(rename = "lastSuccess")]
+    last_success: Option<String>,
+    #[serde(rename = "lastFailure")]
+    last_failure: Option<String>,
+    #[serde(rename = "lastError")]
+    last_error: Option<&'a String>,
+    running: Option<u64>,
+}
+
+fn format_timespec(ts: time::Timespec) -> String {
+    format!("{}", time::at_utc(time::Timespec::new(ts.sec, 0)).rfc3339())
 }
 
 #[derive(Serialize, Debug)]
 struct Status<'a> {
     #[serde(rename = "lastSync")]
-    last_sync: Option<LastSync>,
+    last_sync: Option<LastSync<'a>>,
     manifests: Vec<&'a String>,
+    #[serde(rename = "lastModified")]
+    last_modified: HashMap<&'a String, String>,
     errors: &'a HashMap<String, String>,
+    refresh: Refresh<'a>,
+    pinned: &'a BTreeMap<String, Vec<String>>,
 }
 
 pub fn create_status_string(marian: &Marian) -> String {
     let index = marian.index.read().unwrap();
+    let refresh_status = marian.refresh_status.lock().unwrap();
     let last_sync = match index.finished.sec {
         0 => None,
         secs => {
-            let time = time::at_utc(time::Timespec::new(secs as i64, 0));
+            let time = time::at_utc(time::Timespec::new(secs, 0));
+            let mut rebuilt: Vec<_> = index.rebuilt.iter().collect();
+            rebuilt.sort();
             Some(LastSync {
                 finished: format!("{}", time.rfc3339()),
+                rebuilt,
             })
         }
     };
 
+    let last_modified = index
+        .last_modified
+        .iter()
+        .map(|(search_property, mtime)| {
+            let mtime = time::at_utc(timespec_from(mtime));
+            (search_property, format!("{}", mtime.rfc3339()))
+        })
+        .collect();
+
     let status = Status {
         last_sync,
         manifests: index.manifests.iter().collect(),
+        last_modified,
         errors: &index.manifest_errors,
+        refresh: Refresh {
+            last_success: refresh_status.last_success.map(format_timespec),
+            last_failure: refresh_status.last_failure.map(format_timespec),
+            last_error: refresh_status.last_error.as_ref(),
+            running: refresh_status.running,
+        },
+        pinned: index.pinned.rules(),
     };
 
     serde_json::to_string(&status).unwrap()
 }
+
+/// Describe a refresh job. A job we no longer remember is reported as expired.
+pub fn create_refresh_job_string(id: u64, state: Option<&JobState>) -> String {
+    let job = match state {
+        Some(&JobState::Running) => json!({"id": id, "state": "running"}),
+        Some(&JobState::Succeeded) => json!({"id": id, "state": "succeeded"}),
+        Some(&JobState::Failed(ref msg)) => json!({"id": id, "state": "failed", "error": msg}),
+        None => json!({"id": id, "state": "expired"}),
+    };
+
+    serde_json::to_string(&job).unwrap()
+}
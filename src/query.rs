@@ -1,10 +1,15 @@
 use regex::Regex;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::{cmp, iter, vec};
 use stemmer::{is_stop_word, stem, tokenize};
 
+/// The furthest apart that terms joined by NEAR/n may be. Larger values of n
+/// are reduced to this.
+const MAX_NEAR_DISTANCE: u32 = 100;
+
 lazy_static! {
-    static ref PAT_QUERY_PARTS: Regex =
-        Regex::new(r#""|[^"\s]+"#).expect("Failed to compile query regex");
+    static ref PAT_QUERY_PARTS: Regex = Regex::new(r#"([+-]?)((?:\w+:)?"[^"]*"?|[()]|[^\s()"]+)"#)
+        .expect("Failed to compile query regex");
 }
 
 /// Return true if there is a configuration of numbers in the `tree` that
@@ -28,110 +33,534 @@ fn have_contiguous_path(tree: &[&[u32]], last_candidate: Option<u32>) -> bool {
     false
 }
 
+/// Return true if there is a choice of one number from each sorted row of
+/// `tree` where each is distinct from, and within the corresponding entry of
+/// `distances` of, the number chosen from the row before it.
+fn have_nearby_path(tree: &[&[u32]], distances: &[u32]) -> bool {
+    let mut reachable = match tree.first() {
+        Some(row) => row.to_vec(),
+        None => return true,
+    };
+
+    // Keep each number near a reachable number from the row before, sliding a
+    // window over that row so that each row is only scanned once.
+    for (row, &distance) in tree[1..].iter().zip(distances) {
+        let mut next = vec![];
+        let (mut start, mut end) = (0, 0);
+        for &element in row.iter() {
+            while start < reachable.len() && reachable[start].saturating_add(distance) < element {
+                start += 1;
+            }
+
+            while end < reachable.len() && reachable[end] <= element.saturating_add(distance) {
+                end += 1;
+            }
+
+            let window = &reachable[start..end];
+            if window.len() > 1 || (window.len() == 1 && window[0] != element) {
+                next.push(element);
+            }
+        }
+
+        if next.is_empty() {
+            return false;
+        }
+
+        reachable = next;
+    }
+
+    true
+}
+
 /// Check if the given `phrase_components` appear in contiguous positions
-/// within the keywords map.
-fn have_contiguous_keywords(
+/// within a document, optionally only within one field.
+fn have_contiguous_keywords<C: Candidate>(
     phrase_components: &[String],
-    keywords: &HashMap<&String, &[u32]>,
+    field: Option<&str>,
+    candidate: &C,
 ) -> bool {
-    let mut path = vec![];
+    let mut positions = vec![];
 
     for component in phrase_components {
-        if let Some(&positions) = keywords.get(component) {
-            path.push(positions);
-        } else {
-            return false;
+        match candidate.positions(component, field) {
+            Some(p) => positions.push(p),
+            None => return false,
         }
     }
 
+    let path: Vec<&[u32]> = positions.iter().map(|p| p.as_slice()).collect();
     have_contiguous_path(&path, None)
 }
 
+/// Check if each of the given `terms` appears within the corresponding entry
+/// of `distances` of the term before it, in either order, within one field.
+fn have_nearby_keywords<C: Candidate>(terms: &[String], distances: &[u32], candidate: &C) -> bool {
+    candidate.fields().into_iter().any(|field| {
+        let mut positions = vec![];
+
+        for term in terms {
+            match candidate.positions(term, Some(field)) {
+                Some(p) => positions.push(p),
+                None => return false,
+            }
+        }
+
+        let path: Vec<&[u32]> = positions.iter().map(|p| p.as_slice()).collect();
+        have_nearby_path(&path, distances)
+    })
+}
+
+/// A document that may match a query.
+pub trait Candidate {
+    /// Return true if the document contains `term`, within `field` if one is
+    /// given. If `expand` is set, the indexed terms that the query's prefix
+    /// expansion policy expands `term` to also count.
+    fn has_term(&self, term: &str, field: Option<&str>, expand: bool) -> bool;
+
+    /// Return the positions of exactly `term`, within `field` if one is given.
+    fn positions(&self, term: &str, field: Option<&str>) -> Option<Vec<u32>>;
+
+    /// Return the names of the fields that the document has.
+    fn fields(&self) -> Vec<&str>;
+}
+
+/// How a clause constrains the documents matching its group.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Occurrence {
+    Should,
+    Must,
+    MustNot,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Expression {
+    /// A stemmed term, optionally only within one field.
+    Term(String, Option<String>),
+
+    /// The stemmed terms of a phrase, which must appear contiguously.
+    Phrase(Vec<String>, Option<String>),
+
+    /// Stemmed terms, each of which must appear within the corresponding
+    /// distance of the term before it, as in `shard NEAR/3 key`.
+    Near(Vec<String>, Vec<u32>),
+
+    /// Matches if every Must clause matches and no MustNot clause does. If
+    /// there are no Must clauses, at least one Should clause must match.
+    Group(Vec<(Occurrence, Expression)>),
+}
+
+impl Expression {
+    /// Return true if the expression can never constrain a document, as with
+    /// a group or phrase made only of stop words.
+    fn is_empty(&self) -> bool {
+        match *self {
+            Expression::Term(..) => false,
+            Expression::Phrase(ref parts, _) | Expression::Near(ref parts, _) => parts.is_empty(),
+            Expression::Group(ref clauses) => clauses.is_empty(),
+        }
+    }
+
+    /// Return true if a document matches. Terms are prefix expanded unless
+    /// `excluding`, so that excluding a word doesn't also exclude every
+    /// longer word beginning with it.
+    pub fn matches<C: Candidate>(&self, candidate: &C, excluding: bool) -> bool {
+        match *self {
+            Expression::Term(ref term, ref field) => {
+                candidate.has_term(term, field.as_ref().map(|f| f.as_str()), !excluding)
+            }
+            Expression::Phrase(ref parts, ref field) => {
+                have_contiguous_keywords(parts, field.as_ref().map(|f| f.as_str()), candidate)
+            }
+            Expression::Near(ref terms, ref distances) => {
+                have_nearby_keywords(terms, distances, candidate)
+            }
+            Expression::Group(ref clauses) => {
+                let mut has_required = false;
+                let mut any_optional: Option<bool> = None;
+                for &(occurrence, ref expression) in clauses {
+                    let negated = occurrence == Occurrence::MustNot;
+                    let is_match = expression.matches(candidate, excluding != negated);
+                    match occurrence {
+                        Occurrence::Must if !is_match => return false,
+                        Occurrence::Must => has_required = true,
+                        Occurrence::MustNot if is_match => return false,
+                        Occurrence::MustNot => (),
+                        Occurrence::Should => {
+                            any_optional = Some(any_optional.unwrap_or(false) || is_match)
+                        }
+                    }
+                }
+
+                has_required || any_optional.unwrap_or(true)
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token<'b> {
+    Open,
+    Close,
+    And,
+    Or,
+    Near(u32),
+    Phrase(Option<&'b str>, &'b str),
+    Word(Option<&'b str>, &'b str),
+}
+
+type Tokens<'b> = iter::Peekable<vec::IntoIter<(Option<Occurrence>, Token<'b>)>>;
+
+/// Split a field name from the front of a word or phrase, if it names one of `fields`.
+fn split_field<'b>(body: &'b str, fields: &[&str]) -> (Option<&'b str>, &'b str) {
+    if let Some(i) = body.find(':') {
+        if i + 1 < body.len() && fields.contains(&&body[..i]) {
+            return (Some(&body[..i]), &body[i + 1..]);
+        }
+    }
+
+    (None, body)
+}
+
+fn lex<'b>(query_string: &'b str, fields: &[&str]) -> Tokens<'b> {
+    let mut tokens = vec![];
+    for caps in PAT_QUERY_PARTS.captures_iter(query_string) {
+        let body = caps.get(2).unwrap().as_str();
+
+        // A doubled sign, as in --fork, is part of the word
+        if body.starts_with('+') || body.starts_with('-') {
+            tokens.push((None, Token::Word(None, caps.get(0).unwrap().as_str())));
+            continue;
+        }
+
+        let occurrence = match caps.get(1).unwrap().as_str() {
+            "+" => Some(Occurrence::Must),
+            "-" => Some(Occurrence::MustNot),
+            _ => None,
+        };
+
+        let (field, body) = split_field(body, fields);
+        let token = match body {
+            "(" => Token::Open,
+            ")" => Token::Close,
+            "AND" if occurrence.is_none() => Token::And,
+            "OR" if occurrence.is_none() => Token::Or,
+            _ if occurrence.is_none() && body.starts_with("NEAR/") => {
+                match body["NEAR/".len()..].parse() {
+                    Ok(distance) if distance > 0 => {
+                        Token::Near(cmp::min(distance, MAX_NEAR_DISTANCE))
+                    }
+                    _ => Token::Word(field, body),
+                }
+            }
+            _ if body.starts_with('"') => Token::Phrase(field, body.trim_matches('"')),
+            _ => match body.find(":\"") {
+                // A phrase following something that isn't a field name
+                Some(i) => {
+                    tokens.push((None, Token::Word(None, &body[..i + 1])));
+                    Token::Phrase(None, body[i + 1..].trim_matches('"'))
+                }
+                None => Token::Word(field, body),
+            },
+        };
+
+        tokens.push((occurrence, token));
+    }
+
+    tokens.into_iter().peekable()
+}
+
+/// Join the operands of an AND or OR into a single clause. Excluded operands
+/// remain excluded; the rest take the given occurrence.
+fn combine(
+    mut operands: Vec<(Occurrence, Expression)>,
+    occurrence: Occurrence,
+) -> Option<(Occurrence, Expression)> {
+    operands.retain(|&(_, ref expression)| !expression.is_empty());
+    if operands.len() <= 1 {
+        return operands.pop();
+    }
+
+    let clauses = operands
+        .into_iter()
+        .map(|(operand_occurrence, expression)| match operand_occurrence {
+            Occurrence::MustNot => (Occurrence::MustNot, expression),
+            _ => (occurrence, expression),
+        })
+        .collect();
+
+    // An explicit AND or OR is a constraint, not just a ranking hint
+    Some((Occurrence::Must, Expression::Group(clauses)))
+}
+
+/// A parsed search query. Bare terms are optional and only affect ranking;
+/// terms prefixed with + are required and those with - are excluded. Quoted
+/// phrases are required unless excluded or joined by OR. Terms joined by
+/// NEAR/n must appear within n tokens of each other, for n up to
+/// `MAX_NEAR_DISTANCE`. NEAR binds more tightly
+/// than AND, which binds more tightly than OR, and parentheses group clauses.
+/// A term or phrase prefixed
+/// with a field name and a colon, as in title:aggregation, only matches
+/// within that field.
 pub struct Query<'a> {
+    /// The unstemmed terms that should contribute to relevancy.
     pub terms: HashSet<String>,
+
+    /// The fields that any field-scoped terms are restricted to. Terms that
+    /// are not listed may match in any field.
+    pub term_fields: HashMap<String, BTreeSet<String>>,
+
+    /// The unstemmed final term, unless followed by anything else: the user
+    /// may not have finished typing it.
+    pub prefix_term: Option<String>,
+
     pub phrases: Vec<String>,
     pub stemmed_phrases: Vec<Vec<String>>,
+    pub clauses: Vec<(Occurrence, Expression)>,
     pub search_properties: &'a [&'a str],
 }
 
 impl<'a> Query<'a> {
-    pub fn new(query_string: &str, search_properties: &'a [&str]) -> Self {
+    /// Parse a query, treating a prefix naming one of `fields` as a field scope.
+    pub fn new(query_string: &str, search_properties: &'a [&str], fields: &[&str]) -> Self {
         let mut query = Self {
             terms: HashSet::new(),
+            term_fields: HashMap::new(),
+            prefix_term: None,
             phrases: vec![],
             stemmed_phrases: vec![],
+            clauses: vec![],
             search_properties,
         };
 
-        let mut phrase: Option<String> = None;
-        let mut end_phrase = false;
-        for m in PAT_QUERY_PARTS.find_iter(query_string) {
-            let match_str = m.as_str();
-
-            match phrase {
-                Some(ref mut s) => if match_str == "\"" {
-                    end_phrase = true;
-                } else {
-                    query.add_term(match_str);
-                    s.push_str(match_str);
-                    s.push(' ');
-                },
-                None => {
-                    if match_str == "\"" {
-                        phrase = Some(String::new());
-                        continue;
+        let mut tokens = lex(query_string, fields);
+        query.clauses = query.parse_group(&mut tokens, false, false);
+        if !query_string.ends_with(char::is_alphanumeric) {
+            query.prefix_term = None;
+        }
+
+        query
+    }
+
+    /// Return true if the query has any required or excluded clauses.
+    pub fn is_constrained(&self) -> bool {
+        self.clauses
+            .iter()
+            .any(|&(occurrence, _)| occurrence != Occurrence::Should)
+    }
+
+    /// Return true if a document satisfies every required and excluded clause.
+    /// Unlike in a nested group, optional clauses only affect ranking.
+    pub fn matches<C: Candidate>(&self, candidate: &C) -> bool {
+        self.clauses
+            .iter()
+            .all(|&(occurrence, ref expression)| match occurrence {
+                Occurrence::Should => true,
+                Occurrence::Must => expression.matches(candidate, false),
+                Occurrence::MustNot => !expression.matches(candidate, true),
+            })
+    }
+
+    /// Parse clauses up to the end of the query, or the closing parenthesis
+    /// of a nested group. Unbalanced parentheses and dangling operators are ignored.
+    fn parse_group(
+        &mut self,
+        tokens: &mut Tokens,
+        negated: bool,
+        nested: bool,
+    ) -> Vec<(Occurrence, Expression)> {
+        let mut clauses = vec![];
+        loop {
+            match tokens.peek() {
+                None => break,
+                Some(&(_, Token::Close)) => {
+                    tokens.next();
+                    if nested {
+                        break;
                     }
 
-                    query.add_term(match_str);
+                    continue;
+                }
+                Some(&(_, Token::And)) | Some(&(_, Token::Or)) | Some(&(_, Token::Near(_))) => {
+                    tokens.next();
+                    continue;
                 }
+                _ => (),
             }
 
-            if end_phrase {
-                if let Some(phrase) = phrase {
-                    query.add_phrase(phrase);
-                }
+            if let Some(clause) = self.parse_disjunction(tokens, negated) {
+                clauses.push(clause);
+            }
+        }
+
+        clauses
+    }
 
-                phrase = None;
-                end_phrase = false;
+    fn parse_disjunction(
+        &mut self,
+        tokens: &mut Tokens,
+        negated: bool,
+    ) -> Option<(Occurrence, Expression)> {
+        let mut operands = vec![self.parse_conjunction(tokens, negated)?];
+        while let Some(&(_, Token::Or)) = tokens.peek() {
+            tokens.next();
+            match self.parse_conjunction(tokens, negated) {
+                Some(operand) => operands.push(operand),
+                None => break,
             }
         }
 
-        if let Some(phrase) = phrase {
-            query.add_phrase(phrase);
+        combine(operands, Occurrence::Should)
+    }
+
+    fn parse_conjunction(
+        &mut self,
+        tokens: &mut Tokens,
+        negated: bool,
+    ) -> Option<(Occurrence, Expression)> {
+        let mut operands = vec![self.parse_proximity(tokens, negated)?];
+        while let Some(&(_, Token::And)) = tokens.peek() {
+            tokens.next();
+            match self.parse_proximity(tokens, negated) {
+                Some(operand) => operands.push(operand),
+                None => break,
+            }
         }
 
-        query
+        combine(operands, Occurrence::Must)
     }
 
-    /// Return true if the exact phrases in the query appear in ANY of the fields
-    /// appearing in the match.
-    pub fn check_phrases(&self, tokens: &HashMap<&String, &[u32]>) -> bool {
-        for phrase_tokens in &self.stemmed_phrases {
-            if !have_contiguous_keywords(phrase_tokens.as_slice(), tokens) {
-                return false;
+    /// Parse terms joined by NEAR/n. Operands other than single unscoped
+    /// terms can't be located, so are joined as if by AND.
+    fn parse_proximity(
+        &mut self,
+        tokens: &mut Tokens,
+        negated: bool,
+    ) -> Option<(Occurrence, Expression)> {
+        let mut operands = vec![self.parse_unary(tokens, negated)?];
+        let mut distances = vec![];
+        while let Some(&(_, Token::Near(distance))) = tokens.peek() {
+            tokens.next();
+            match self.parse_unary(tokens, negated) {
+                Some(operand) => operands.push(operand),
+                None => break,
             }
+
+            distances.push(distance);
+        }
+
+        if distances.is_empty() {
+            return operands.pop();
         }
 
-        true
+        let terms: Vec<String> = operands
+            .iter()
+            .filter_map(|operand| match *operand {
+                (Occurrence::MustNot, _) => None,
+                (_, Expression::Term(ref term, None)) => Some(term.to_owned()),
+                _ => None,
+            })
+            .collect();
+
+        if terms.len() < operands.len() {
+            return combine(operands, Occurrence::Must);
+        }
+
+        Some((Occurrence::Must, Expression::Near(terms, distances)))
     }
 
-    fn add_phrase(&mut self, mut phrase: String) {
-        if phrase.as_bytes().ends_with(b" ") {
-            phrase.pop();
+    fn parse_unary(
+        &mut self,
+        tokens: &mut Tokens,
+        negated: bool,
+    ) -> Option<(Occurrence, Expression)> {
+        match tokens.peek() {
+            None
+            | Some(&(_, Token::Close))
+            | Some(&(_, Token::And))
+            | Some(&(_, Token::Or))
+            | Some(&(_, Token::Near(_))) => return None,
+            _ => (),
         }
 
+        let (modifier, token) = tokens.next().unwrap();
+        let negated = negated || modifier == Some(Occurrence::MustNot);
+        let (occurrence, expression) = match token {
+            Token::Open => (
+                Occurrence::Should,
+                Expression::Group(self.parse_group(tokens, negated, true)),
+            ),
+            Token::Phrase(field, phrase) => {
+                (Occurrence::Must, self.add_phrase(phrase, field, negated))
+            }
+            Token::Word(field, word) => (Occurrence::Should, self.add_term(word, field, negated)),
+            Token::Close | Token::And | Token::Or | Token::Near(_) => unreachable!(),
+        };
+
+        Some((modifier.unwrap_or(occurrence), expression))
+    }
+
+    fn add_phrase(&mut self, phrase: &str, field: Option<&str>, negated: bool) -> Expression {
+        self.prefix_term = None;
+        let words: Vec<_> = phrase.split_whitespace().collect();
+        let phrase = words.join(" ");
         let parts: Vec<_> = tokenize(&phrase, false)
             .iter()
             .filter(|term| !is_stop_word(term))
             .map(|term| stem(term))
             .collect();
-        self.stemmed_phrases.push(parts);
-        self.phrases.push(phrase);
+
+        if !negated {
+            for word in words {
+                for part in tokenize(word, false) {
+                    self.add_scoring_term(part, field);
+                }
+            }
+
+            self.stemmed_phrases.push(parts.clone());
+            self.phrases.push(phrase);
+        }
+
+        Expression::Phrase(parts, field.map(|f| f.to_owned()))
     }
 
-    fn add_term(&mut self, term: &str) {
-        for part in tokenize(term, false) {
-            self.terms.insert(part);
+    /// Score a term, restricting it to `field` unless it already appears
+    /// elsewhere in the query without one.
+    fn add_scoring_term(&mut self, term: String, field: Option<&str>) {
+        match field {
+            Some(field) => if !self.terms.contains(&term) || self.term_fields.contains_key(&term) {
+                self.term_fields
+                    .entry(term.to_owned())
+                    .or_insert_with(BTreeSet::new)
+                    .insert(field.to_owned());
+            },
+            None => {
+                self.term_fields.remove(&term);
+            }
+        }
+
+        self.terms.insert(term);
+    }
+
+    /// Add a word, which may contain several tokens, any of which may match.
+    fn add_term(&mut self, word: &str, field: Option<&str>, negated: bool) -> Expression {
+        let mut clauses = vec![];
+        self.prefix_term = None;
+        for part in tokenize(word, false) {
+            if !is_stop_word(&part) {
+                let term = Expression::Term(stem(&part), field.map(|f| f.to_owned()));
+                clauses.push((Occurrence::Should, term));
+            }
+
+            if !negated {
+                self.prefix_term = Some(part.to_owned());
+                self.add_scoring_term(part, field);
+            }
+        }
+
+        if clauses.len() == 1 {
+            clauses.pop().unwrap().1
+        } else {
+            Expression::Group(clauses)
         }
     }
 }
@@ -140,9 +569,43 @@ impl<'a> Query<'a> {
 mod tests {
     use super::*;
 
+    impl<'a> Candidate for HashMap<&'a String, &'a [u32]> {
+        fn has_term(&self, term: &str, _: Option<&str>, _: bool) -> bool {
+            self.contains_key(&term.to_owned())
+        }
+
+        fn positions(&self, term: &str, _: Option<&str>) -> Option<Vec<u32>> {
+            self.get(&term.to_owned()).map(|positions| positions.to_vec())
+        }
+
+        fn fields(&self) -> Vec<&str> {
+            vec!["text"]
+        }
+    }
+
+    /// A document containing the given terms, in any field. Expanded terms
+    /// match every term beginning with them.
+    struct Terms(&'static [&'static str]);
+
+    impl Candidate for Terms {
+        fn has_term(&self, term: &str, _: Option<&str>, expand: bool) -> bool {
+            self.0
+                .iter()
+                .any(|t| *t == term || (expand && t.starts_with(term)))
+        }
+
+        fn positions(&self, _: &str, _: Option<&str>) -> Option<Vec<u32>> {
+            None
+        }
+
+        fn fields(&self) -> Vec<&str> {
+            vec!["text"]
+        }
+    }
+
     #[test]
     fn test_single_term() {
-        let query = Query::new("foo", &[]);
+        let query = Query::new("foo", &[], &[]);
         assert_eq!(query.terms, hashset!["foo".to_owned()]);
         assert_eq!(query.search_properties, &[] as &[&str]);
         assert_eq!(query.phrases, Vec::<String>::new());
@@ -151,14 +614,14 @@ mod tests {
     #[test]
     fn test_whitespace() {
         // it should delimit terms with any standard whitespace characters
-        let query = Query::new("foo   \t  bar", &[]);
+        let query = Query::new("foo   \t  bar", &[], &[]);
         assert_eq!(query.terms, hashset!["foo".to_owned(), "bar".to_owned()]);
         assert_eq!(query.phrases, Vec::<String>::new());
     }
 
     #[test]
     fn test_multi_word_phrases() {
-        let query = Query::new("foo \"one phrase\" bar \"second phrase\"", &[]);
+        let query = Query::new("foo \"one phrase\" bar \"second phrase\"", &[], &[]);
         assert_eq!(
             query.terms,
             hashset![
@@ -177,7 +640,7 @@ mod tests {
 
     #[test]
     fn test_adjacent_phrases() {
-        let query = Query::new("\"introduce the\" \"officially supported\"", &[]);
+        let query = Query::new("\"introduce the\" \"officially supported\"", &[], &[]);
         assert_eq!(
             query.terms,
             hashset![
@@ -206,7 +669,7 @@ mod tests {
     #[test]
     fn test_phrase_fragment() {
         // it should handle a phrase fragment as a single phrase
-        let query = Query::new("\"officially supported", &[]);
+        let query = Query::new("\"officially supported", &[], &[]);
         assert_eq!(
             query.terms,
             hashset!["officially".to_owned(), "supported".to_owned()]
@@ -217,19 +680,19 @@ mod tests {
     #[test]
     fn test_check_phrases() {
         // it should match phrases with adjacent words
-        let query = Query::new("\"Quoth the raven\"", &[]);
+        let query = Query::new("\"Quoth the raven\"", &[], &[]);
         let s1 = "quoth".to_owned();
         let s2 = "raven".to_owned();
         let v1 = vec![0, 5];
         let v2 = vec![8, 1];
         let token_positions = hashmap![&s1 => v1.as_slice(), &s2 => v2.as_slice()];
-        assert_eq!(query.check_phrases(&token_positions), true);
+        assert_eq!(query.matches(&token_positions), true);
     }
 
     #[test]
     fn test_check_phrases_negative() {
         // it should refuse phrases without adjacent words
-        let query = Query::new("\"foo bar\" \"Quoth the raven\"", &[]);
+        let query = Query::new("\"foo bar\" \"Quoth the raven\"", &[], &[]);
         let s1 = "quoth".to_owned();
         let s2 = "raven".to_owned();
         let s3 = "foo".to_owned();
@@ -244,6 +707,234 @@ mod tests {
             &s2 => v2.as_slice(),
             &s3 => v3.as_slice(),
             &s4 => v4.as_slice(),];
-        assert_eq!(query.check_phrases(&token_positions), false);
+        assert_eq!(query.matches(&token_positions), false);
+    }
+
+    fn term(s: &str) -> Expression {
+        Expression::Term(s.to_owned(), None)
+    }
+
+    #[test]
+    fn test_operators() {
+        let query = Query::new("+sharding -atlas replica", &[], &[]);
+        assert_eq!(
+            query.clauses,
+            vec![
+                (Occurrence::Must, term("shard")),
+                (Occurrence::MustNot, term("atlas")),
+                (Occurrence::Should, term("replic")),
+            ]
+        );
+        assert_eq!(
+            query.terms,
+            hashset!["sharding".to_owned(), "replica".to_owned()]
+        );
+        assert!(query.is_constrained());
+
+        // Doubled signs are part of the word
+        let query = Query::new("--fork", &[], &[]);
+        assert_eq!(query.clauses, vec![(Occurrence::Should, term("fork"))]);
+        assert!(!query.is_constrained());
+
+        let query = Query::new("-\"sharded cluster\"", &[], &[]);
+        assert_eq!(
+            query.clauses,
+            vec![(
+                Occurrence::MustNot,
+                Expression::Phrase(vec!["shard".to_owned(), "cluster".to_owned()], None),
+            )]
+        );
+        assert!(query.terms.is_empty());
+        assert!(query.phrases.is_empty());
+    }
+
+    #[test]
+    fn test_grouping() {
+        // AND binds more tightly than OR
+        let query = Query::new("atlas OR compass AND oplog", &[], &[]);
+        assert_eq!(
+            query.clauses,
+            vec![(
+                Occurrence::Must,
+                Expression::Group(vec![
+                    (Occurrence::Should, term("atlas")),
+                    (
+                        Occurrence::Should,
+                        Expression::Group(vec![
+                            (Occurrence::Must, term("compass")),
+                            (Occurrence::Must, term("oplog")),
+                        ]),
+                    ),
+                ]),
+            )]
+        );
+
+        let query = Query::new("mongodb -(atlas OR compass) (the)", &[], &[]);
+        assert_eq!(
+            query.clauses,
+            vec![
+                (Occurrence::Should, term("mongodb")),
+                (
+                    Occurrence::MustNot,
+                    Expression::Group(vec![(
+                        Occurrence::Must,
+                        Expression::Group(vec![
+                            (Occurrence::Should, term("atlas")),
+                            (Occurrence::Should, term("compass")),
+                        ]),
+                    )]),
+                ),
+            ]
+        );
+        assert_eq!(query.terms, hashset!["mongodb".to_owned(), "the".to_owned()]);
+
+        // Unbalanced parentheses and dangling operators are ignored
+        let query = Query::new(") OR foo (bar AND", &[], &[]);
+        assert_eq!(
+            query.clauses,
+            vec![
+                (Occurrence::Should, term("foo")),
+                (
+                    Occurrence::Should,
+                    Expression::Group(vec![(Occurrence::Should, term("bar"))]),
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_matches() {
+        let query = Query::new("+shard -atlas oplog", &[], &[]);
+        assert!(query.matches(&Terms(&["shard"])));
+        assert!(query.matches(&Terms(&["shard", "oplog"])));
+        assert!(!query.matches(&Terms(&["oplog"])));
+        assert!(!query.matches(&Terms(&["shard", "atlas"])));
+
+        let query = Query::new("compass OR oplog", &[], &[]);
+        assert!(query.matches(&Terms(&["oplog"])));
+        assert!(!query.matches(&Terms(&["shard"])));
+
+        let query = Query::new("compass AND oplog", &[], &[]);
+        assert!(query.matches(&Terms(&["compass", "oplog"])));
+        assert!(!query.matches(&Terms(&["oplog"])));
+
+        let query = Query::new("+(atlas -compass)", &[], &[]);
+        assert!(query.matches(&Terms(&["atlas"])));
+        assert!(!query.matches(&Terms(&["atlas", "compass"])));
+        assert!(!query.matches(&Terms(&[])));
+
+        // Excluded terms are never expanded, even within a required group
+        let query = Query::new("+shard -atlas", &[], &[]);
+        assert!(query.matches(&Terms(&["sharding", "atlases"])));
+        let query = Query::new("+(shard -atlas)", &[], &[]);
+        assert!(query.matches(&Terms(&["sharding", "atlases"])));
+        let query = Query::new("-(shard -atlas)", &[], &[]);
+        assert!(query.matches(&Terms(&["shard", "atlases"])));
+        assert!(query.matches(&Terms(&["sharding"])));
+    }
+
+    #[test]
+    fn test_fields() {
+        let fields = ["title", "text"];
+        let query = Query::new(r#"title:aggregation text:"oplog size" bogus:atlas"#, &[], &fields);
+        assert_eq!(
+            query.clauses,
+            vec![
+                (
+                    Occurrence::Should,
+                    Expression::Term("aggreg".to_owned(), Some("title".to_owned())),
+                ),
+                (
+                    Occurrence::Must,
+                    Expression::Phrase(
+                        vec!["oplog".to_owned(), "size".to_owned()],
+                        Some("text".to_owned()),
+                    ),
+                ),
+                (
+                    Occurrence::Should,
+                    Expression::Group(vec![
+                        (Occurrence::Should, term("bogus")),
+                        (Occurrence::Should, term("atlas")),
+                    ]),
+                ),
+            ]
+        );
+        assert_eq!(query.term_fields["aggregation"], btreeset!["title".to_owned()]);
+        assert_eq!(query.term_fields["oplog"], btreeset!["text".to_owned()]);
+        assert!(!query.term_fields.contains_key("atlas"));
+
+        // An unscoped occurrence lifts the restriction
+        let query = Query::new("title:atlas atlas", &[], &fields);
+        assert!(query.term_fields.is_empty());
+        let query = Query::new("atlas title:atlas", &[], &fields);
+        assert!(query.term_fields.is_empty());
+
+        // Unknown field names before a phrase are ordinary terms
+        let query = Query::new(r#"bogus:"oplog size""#, &[], &fields);
+        assert_eq!(query.clauses[1].1, Expression::Phrase(vec!["oplog".to_owned(), "size".to_owned()], None));
+        assert!(query.terms.contains("bogus"));
+    }
+
+    #[test]
+    fn test_near() {
+        let query = Query::new("quoth NEAR/2 raven", &[], &[]);
+        assert_eq!(
+            query.clauses,
+            vec![(
+                Occurrence::Must,
+                Expression::Near(vec!["quoth".to_owned(), "raven".to_owned()], vec![2]),
+            )]
+        );
+        assert_eq!(query.terms, hashset!["quoth".to_owned(), "raven".to_owned()]);
+
+        let quoth = "quoth".to_owned();
+        let raven = "raven".to_owned();
+        let matches = |quoth_positions: &[u32], raven_positions: &[u32]| {
+            query.matches(&hashmap![&quoth => quoth_positions, &raven => raven_positions])
+        };
+        assert!(matches(&[1], &[3]));
+        assert!(matches(&[5], &[4]));
+        assert!(!matches(&[1, 9], &[4]));
+
+        // Each term must be near the one before it, at a different position
+        let tree: &[&[u32]] = &[&[1, 20], &[3, 18], &[17]];
+        assert!(have_nearby_path(tree, &[2, 1]));
+        assert!(!have_nearby_path(tree, &[1, 1]));
+        assert!(!have_nearby_path(&[&[4], &[4]], &[2]));
+        assert!(have_nearby_path(&[&[4, 5], &[4]], &[2]));
+
+        // Long chains that fail at the last step don't take forever
+        let positions: Vec<u32> = (0..10000).collect();
+        let tree: &[&[u32]] = &[&positions, &positions, &positions, &[100000]];
+        assert!(!have_nearby_path(tree, &[MAX_NEAR_DISTANCE, MAX_NEAR_DISTANCE, 1]));
+
+        let query = Query::new("quoth NEAR/1000000 raven", &[], &[]);
+        assert_eq!(
+            query.clauses[0].1,
+            Expression::Near(vec!["quoth".to_owned(), "raven".to_owned()], vec![MAX_NEAR_DISTANCE])
+        );
+
+        // Operands that aren't single terms are joined as if by AND
+        let query = Query::new(r#"atlas NEAR/3 "oplog size""#, &[], &[]);
+        assert_eq!(query.clauses[0].0, Occurrence::Must);
+        match query.clauses[0].1 {
+            Expression::Group(ref clauses) => assert_eq!(clauses.len(), 2),
+            ref expression => panic!("Expected a group: {:?}", expression),
+        }
+
+        // Anything else spelled like NEAR is an ordinary term
+        let query = Query::new("atlas NEAR/0 compass near/2 oplog", &[], &[]);
+        assert!(!query.is_constrained());
+        assert!(query.terms.contains("near"));
+    }
+
+    #[test]
+    fn test_prefix_term() {
+        assert_eq!(Query::new("sharded clus", &[], &[]).prefix_term, Some("clus".to_owned()));
+        assert_eq!(Query::new("sharded clus ", &[], &[]).prefix_term, None);
+        assert_eq!(Query::new("\"sharded clus\"", &[], &[]).prefix_term, None);
+        assert_eq!(Query::new("(sharded clus)", &[], &[]).prefix_term, None);
+        assert_eq!(Query::new("sharded -clus", &[], &[]).prefix_term, None);
     }
 }
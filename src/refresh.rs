@@ -0,0 +1,269 @@
+use futures::sync::oneshot;
+use std::any::Any;
+use std::collections::VecDeque;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use time;
+use {refresh_corpus, Marian};
+
+/// How often to check watched manifest sources for changes, in seconds.
+const WATCH_POLL_INTERVAL: u64 = 2;
+
+/// How many refresh jobs to remember for GET /refresh/<id>.
+const JOB_HISTORY: usize = 16;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum JobState {
+    Running,
+    Succeeded,
+    Failed(String),
+}
+
+/// The outcome of the most recent refresh attempts, and the jobs that
+/// requested them.
+pub struct RefreshStatus {
+    pub last_success: Option<time::Timespec>,
+    pub last_failure: Option<time::Timespec>,
+    pub last_error: Option<String>,
+    pub running: Option<u64>,
+    next_job_id: u64,
+    jobs: VecDeque<(u64, JobState)>,
+
+    /// Those waiting for a running job to finish.
+    waiters: Vec<oneshot::Sender<JobState>>,
+}
+
+impl RefreshStatus {
+    pub fn new() -> Self {
+        Self {
+            last_success: None,
+            last_failure: None,
+            last_error: None,
+            running: None,
+            next_job_id: 1,
+            jobs: VecDeque::new(),
+            waiters: vec![],
+        }
+    }
+
+    pub fn record(&mut self, result: &Result<(), String>) {
+        match *result {
+            Ok(()) => self.last_success = Some(time::get_time()),
+            Err(ref msg) => {
+                self.last_failure = Some(time::get_time());
+                self.last_error = Some(msg.to_owned());
+            }
+        }
+    }
+
+    /// Begin a new refresh job, or join the one that is already running.
+    /// Returns the job's id, and whether the caller is responsible for running it.
+    pub fn begin_job(&mut self) -> (u64, bool) {
+        if let Some(id) = self.running {
+            return (id, false);
+        }
+
+        let id = self.next_job_id;
+        self.next_job_id += 1;
+        self.running = Some(id);
+        self.jobs.push_back((id, JobState::Running));
+        while self.jobs.len() > JOB_HISTORY {
+            self.jobs.pop_front();
+        }
+
+        (id, true)
+    }
+
+    fn finish_job(&mut self, id: u64, result: &Result<(), String>) {
+        let state = match *result {
+            Ok(()) => JobState::Succeeded,
+            Err(ref msg) => JobState::Failed(msg.to_owned()),
+        };
+
+        if let Some(job) = self.jobs.iter_mut().find(|job| job.0 == id) {
+            job.1 = state.clone();
+        }
+
+        self.running = None;
+        for waiter in self.waiters.drain(..) {
+            // The waiter may have hung up; that's fine
+            let _ = waiter.send(state.clone());
+        }
+    }
+
+    /// Return a future resolving to a job's final state, or an error if the job
+    /// is unknown.
+    fn wait_for(&mut self, id: u64) -> oneshot::Receiver<JobState> {
+        let (sender, receiver) = oneshot::channel();
+        match self.job_state(id) {
+            Some(&JobState::Running) => self.waiters.push(sender),
+            Some(state) => {
+                let _ = sender.send(state.clone());
+            }
+            None => (),
+        }
+
+        receiver
+    }
+
+    pub fn job_state(&self, id: u64) -> Option<&JobState> {
+        self.jobs
+            .iter()
+            .find(|job| job.0 == id)
+            .map(|job| &job.1)
+    }
+}
+
+/// Describe the payload of a panic.
+fn panic_message(payload: &Box<Any + Send>) -> String {
+    match payload.downcast_ref::<&str>() {
+        Some(msg) => (*msg).to_owned(),
+        None => match payload.downcast_ref::<String>() {
+            Some(msg) => msg.to_owned(),
+            None => String::from("unknown error"),
+        },
+    }
+}
+
+/// Run a refresh job that the caller has begun, and wake anyone waiting on it.
+/// A panic fails the job rather than leaving it running forever.
+fn run_job(marian: &Marian, id: u64) -> Result<(), String> {
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut corpus = marian.corpus.lock().unwrap();
+        refresh_corpus(marian, &mut corpus)
+    })).unwrap_or_else(|payload| {
+        let result = Err(format!("Refresh panicked: {}", panic_message(&payload)));
+        marian.refresh_status.lock().unwrap().record(&result);
+        result
+    });
+
+    marian.refresh_status.lock().unwrap().finish_job(id, &result);
+    result
+}
+
+/// Start refreshing the index on its own thread, so as not to hold up the
+/// worker pool, or join the refresh that is already in flight. Returns the
+/// job's id without waiting.
+pub fn start(marian: &Arc<Marian>) -> u64 {
+    let (id, is_new) = marian.refresh_status.lock().unwrap().begin_job();
+    if is_new {
+        let marian = Arc::clone(marian);
+        thread::spawn(move || {
+            if let Err(msg) = run_job(&marian, id) {
+                error!("Error loading manifests: {}", msg);
+            }
+        });
+    }
+
+    id
+}
+
+/// Return a future resolving to a refresh job's final state once it finishes.
+/// Nothing blocks while waiting.
+pub fn wait(marian: &Marian, id: u64) -> oneshot::Receiver<JobState> {
+    marian.refresh_status.lock().unwrap().wait_for(id)
+}
+
+/// Refresh the index unless a refresh is already underway, in which case
+/// there's nothing to do: it will pick up any changes.
+fn try_refresh(marian: &Marian) {
+    let (id, is_new) = marian.refresh_status.lock().unwrap().begin_job();
+    if !is_new {
+        info!("Skipping scheduled refresh: a refresh is already running");
+        return;
+    }
+
+    if let Err(msg) = run_job(marian, id) {
+        error!("Error loading manifests: {}", msg);
+    }
+}
+
+/// Start a background thread that refreshes the index every `interval`, and,
+/// if `watch` is set, whenever the manifest source reports a change.
+pub fn spawn_scheduler(marian: Arc<Marian>, interval: Option<Duration>, watch: bool) {
+    if interval.is_none() && !watch {
+        return;
+    }
+
+    let watch_poll_interval = Duration::from_secs(WATCH_POLL_INTERVAL);
+    let poll_interval = match interval {
+        Some(interval) if !watch || interval < watch_poll_interval => interval,
+        _ => watch_poll_interval,
+    };
+
+    thread::spawn(move || {
+        let mut last_refresh = Instant::now();
+        let mut fingerprint = if watch {
+            marian.manifest_loader.fingerprint()
+        } else {
+            None
+        };
+
+        loop {
+            thread::sleep(poll_interval);
+
+            let is_due = interval
+                .map(|interval| last_refresh.elapsed() >= interval)
+                .unwrap_or(false);
+
+            let has_changed = watch && {
+                let new_fingerprint = marian.manifest_loader.fingerprint();
+                let has_changed = new_fingerprint != fingerprint;
+                fingerprint = new_fingerprint;
+                has_changed
+            };
+
+            if is_due || has_changed {
+                try_refresh(&marian);
+                last_refresh = Instant::now();
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::Future;
+
+    #[test]
+    fn test_jobs() {
+        let mut status = RefreshStatus::new();
+        assert_eq!(status.begin_job(), (1, true));
+        assert_eq!(status.begin_job(), (1, false));
+        assert_eq!(status.job_state(1), Some(&JobState::Running));
+
+        status.finish_job(1, &Err("Oops".to_owned()));
+        assert_eq!(status.running, None);
+        assert_eq!(
+            status.job_state(1),
+            Some(&JobState::Failed("Oops".to_owned()))
+        );
+
+        assert_eq!(status.begin_job(), (2, true));
+        let waiter = status.wait_for(2);
+        status.finish_job(2, &Ok(()));
+        assert_eq!(status.job_state(2), Some(&JobState::Succeeded));
+        assert_eq!(waiter.wait(), Ok(JobState::Succeeded));
+        assert_eq!(status.wait_for(2).wait(), Ok(JobState::Succeeded));
+        assert!(status.wait_for(100).wait().is_err());
+
+        for _ in 0..JOB_HISTORY {
+            let (id, _) = status.begin_job();
+            status.finish_job(id, &Ok(()));
+        }
+        assert_eq!(status.job_state(1), None);
+        assert_eq!(status.job_state(2), None);
+        assert_eq!(status.job_state(3), Some(&JobState::Succeeded));
+    }
+
+    #[test]
+    fn test_panic_message() {
+        let payload = panic::catch_unwind(|| panic!("Oops")).unwrap_err();
+        assert_eq!(panic_message(&payload), "Oops");
+        let payload = panic::catch_unwind(|| panic!("Oops {}", 1)).unwrap_err();
+        assert_eq!(panic_message(&payload), "Oops 1");
+    }
+}
@@ -0,0 +1,246 @@
+use config::RankingConfig;
+use std::cmp;
+use std::str::FromStr;
+
+/// What a scorer needs to know about a query term.
+pub struct TermStats {
+    /// 1.0 for terms from the query; less for correlated and prefix-matched terms.
+    pub weight: f32,
+
+    /// The number of distinct terms in the query.
+    pub query_length: u32,
+
+    /// The number of documents containing the term in any field.
+    pub document_frequency: u32,
+    pub document_count: u32,
+}
+
+/// What a scorer needs to know about one field of a matching document.
+pub struct FieldStats {
+    pub weight: f32,
+    pub length_weight: f32,
+
+    /// The number of times the term appears in this field of the document.
+    pub term_frequency: u32,
+
+    /// The number of documents whose field contains the term.
+    pub term_documents: u32,
+
+    /// The number of tokens in this field of the document.
+    pub length: u32,
+    pub average_length: f32,
+    pub total_tokens: u32,
+}
+
+/// A model for how relevant a document is to a single query term.
+pub trait Scorer: Sync {
+    /// Return each field's contribution to the document's relevancy. A
+    /// document's relevancy is the sum across all matched terms and fields.
+    fn score(&self, term: &TermStats, fields: &[FieldStats], ranking: &RankingConfig) -> Vec<f32>;
+}
+
+/// Yuanhua Lv and ChengXiang Zhai. 2011. Lower-bounding term frequency
+/// normalization. In Proceedings of the 20th ACM international
+/// conference on Information and knowledge management (CIKM '11), Bettina
+/// Berendt, Arjen de Vries, Wenfei Fan, Craig Macdonald, Iadh Ounis, and
+/// Ian Ruthven (Eds.). ACM, New York, NY, USA, 7-16. DOI: <https://doi.org/10.1145/2063576.2063584>
+fn dirichlet_plus(
+    term_frequency_in_query: f32,
+    term_frequency_in_doc: u32,
+    term_probability_in_language: f32,
+    doc_length: u32,
+    query_length: u32,
+    mu: f32,
+    delta: f32,
+) -> f32 {
+    // In some fields, the query may never exist, making its probability 0.
+    // This is... weird. Return 0 to avoid NaN since while dirichlet+
+    // prefers rare words, a nonexistent word should probably be ignored.
+    if term_probability_in_language == 0.0 {
+        return 0.0;
+    }
+
+    let term2 = (1.0 + (term_frequency_in_doc as f32 / (mu * term_probability_in_language))).log2();
+    let term2 = term2 + (1.0 + (delta / (mu * term_probability_in_language))).log2();
+
+    let term3 = query_length as f32 * (mu / (doc_length as f32 + mu)).log2();
+
+    (term_frequency_in_query * term2) + term3
+}
+
+/// Scores each field independently with dirichlet+, and sums them.
+pub struct DirichletPlus;
+
+impl Scorer for DirichletPlus {
+    fn score(&self, term: &TermStats, fields: &[FieldStats], ranking: &RankingConfig) -> Vec<f32> {
+        fields
+            .iter()
+            .map(|field| {
+                let term_probability = field.term_documents as f32
+                    / cmp::max(field.total_tokens, ranking.min_field_tokens) as f32;
+
+                // Larger fields yield larger scores, but we want fields to have roughly
+                // equal weight. field.lengthWeight is stupid, but yields good results.
+                dirichlet_plus(
+                    term.weight,
+                    field.term_frequency,
+                    term_probability,
+                    field.length,
+                    term.query_length,
+                    ranking.mu,
+                    ranking.delta,
+                ) * field.weight
+                    * field.length_weight
+            })
+            .collect()
+    }
+}
+
+/// Stephen Robertson, Hugo Zaragoza, and Michael Taylor. 2004. Simple BM25
+/// extension to multiple weighted fields. In Proceedings of the thirteenth ACM
+/// international conference on Information and knowledge management (CIKM '04).
+/// ACM, New York, NY, USA, 42-49. DOI: <https://doi.org/10.1145/1031171.1031181>
+///
+/// Term frequencies are length-normalized and weighted per field, then combined
+/// before saturating, so that repeating a term across fields is not rewarded
+/// more than repeating it within one.
+pub struct BM25F;
+
+impl Scorer for BM25F {
+    fn score(&self, term: &TermStats, fields: &[FieldStats], ranking: &RankingConfig) -> Vec<f32> {
+        let b = ranking.bm25_b;
+        let weighted_frequencies: Vec<f32> = fields
+            .iter()
+            .map(|field| {
+                if field.term_frequency == 0 {
+                    return 0.0;
+                }
+
+                let normalization = 1.0 - b + b * field.length as f32 / field.average_length;
+                field.weight * field.term_frequency as f32 / normalization
+            })
+            .collect();
+
+        let frequency: f32 = weighted_frequencies.iter().sum();
+        if frequency == 0.0 {
+            return vec![0.0; fields.len()];
+        }
+
+        let n = term.document_frequency as f32;
+        let idf = (1.0 + (term.document_count as f32 - n + 0.5) / (n + 0.5)).ln();
+        let score = term.weight * idf * frequency / (ranking.bm25_k1 + frequency);
+
+        // Attribute the score to each field in proportion to its share of the frequency
+        weighted_frequencies
+            .iter()
+            .map(|field_frequency| score * field_frequency / frequency)
+            .collect()
+    }
+}
+
+/// The available scoring models.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub enum ScorerKind {
+    #[serde(rename = "dirichlet_plus")]
+    DirichletPlus,
+
+    #[serde(rename = "bm25")]
+    BM25,
+}
+
+impl ScorerKind {
+    pub fn scorer(self) -> &'static Scorer {
+        match self {
+            ScorerKind::DirichletPlus => &DirichletPlus,
+            ScorerKind::BM25 => &BM25F,
+        }
+    }
+}
+
+impl FromStr for ScorerKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dirichlet_plus" => Ok(ScorerKind::DirichletPlus),
+            "bm25" => Ok(ScorerKind::BM25),
+            _ => Err(format!("Unknown scorer: {}", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn term(document_frequency: u32) -> TermStats {
+        TermStats {
+            weight: 1.0,
+            query_length: 1,
+            document_frequency,
+            document_count: 100,
+        }
+    }
+
+    fn field(weight: f32, term_frequency: u32, length: u32) -> FieldStats {
+        FieldStats {
+            weight,
+            length_weight: 1.0,
+            term_frequency,
+            term_documents: 10,
+            length,
+            average_length: 10.0,
+            total_tokens: 1000,
+        }
+    }
+
+    fn total(scores: Vec<f32>) -> f32 {
+        scores.iter().sum()
+    }
+
+    #[test]
+    fn test_dirichlet_plus() {
+        let ranking = RankingConfig::default();
+        let scores = DirichletPlus.score(&term(10), &[field(1.0, 2, 10), field(1.0, 0, 10)], &ranking);
+        assert_eq!(scores.len(), 2);
+        assert!(scores[0] > scores[1]);
+
+        // Field weights scale each field's score
+        let weighted = DirichletPlus.score(&term(10), &[field(10.0, 2, 10)], &ranking);
+        assert!((weighted[0] - 10.0 * scores[0]).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_bm25f() {
+        let ranking = RankingConfig::default();
+        let score = |term_stats: &TermStats, fields: &[FieldStats]| {
+            total(BM25F.score(term_stats, fields, &ranking))
+        };
+
+        // More occurrences help, but saturate
+        let one = score(&term(10), &[field(1.0, 1, 10)]);
+        let two = score(&term(10), &[field(1.0, 2, 10)]);
+        let three = score(&term(10), &[field(1.0, 3, 10)]);
+        assert!(0.0 < one && one < two && two < three);
+        assert!(three - two < two - one);
+
+        // Rare terms, short fields, and weighty fields score higher
+        assert!(score(&term(1), &[field(1.0, 1, 10)]) > one);
+        assert!(score(&term(10), &[field(1.0, 1, 5)]) > one);
+        assert!(score(&term(10), &[field(5.0, 1, 10)]) > one);
+
+        // Fields are combined before saturation
+        let split = BM25F.score(&term(10), &[field(1.0, 1, 10), field(1.0, 1, 10)], &ranking);
+        assert!((total(split.clone()) - two).abs() < 1e-6);
+        assert_eq!(split[0], split[1]);
+
+        assert_eq!(BM25F.score(&term(10), &[field(1.0, 0, 10)], &ranking), vec![0.0]);
+    }
+
+    #[test]
+    fn test_scorer_kind() {
+        assert_eq!("bm25".parse(), Ok(ScorerKind::BM25));
+        assert_eq!("dirichlet_plus".parse(), Ok(ScorerKind::DirichletPlus));
+        assert!("tfidf".parse::<ScorerKind>().is_err());
+    }
+}
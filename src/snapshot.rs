@@ -0,0 +1,235 @@
+use bincode;
+use fts::FTSIndex;
+use manifest::{Manifest, ManifestError};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::SystemTime;
+
+const MAGIC: [u8; 8] = *b"MARIANIX";
+
+/// Bump whenever the serialized layout of FTSIndex changes.
+pub const SNAPSHOT_VERSION: u32 = 10;
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    magic: [u8; 8],
+    version: u32,
+}
+
+/// Atomically write a snapshot of `index` to `path`.
+pub fn save(path: &Path, index: &FTSIndex) -> Result<(), String> {
+    let tmp_path = path.with_extension("tmp");
+    {
+        let file = File::create(&tmp_path)
+            .map_err(|_| format!("Failed to create snapshot file: {}", tmp_path.display()))?;
+        let mut writer = BufWriter::new(file);
+
+        let header = Header {
+            magic: MAGIC,
+            version: SNAPSHOT_VERSION,
+        };
+        bincode::serialize_into(&mut writer, &header)
+            .and_then(|_| bincode::serialize_into(&mut writer, index))
+            .map_err(|err| format!("Failed to serialize snapshot: {}", err))?;
+        writer
+            .flush()
+            .map_err(|err| format!("Failed to write snapshot: {}", err))?;
+    }
+
+    fs::rename(&tmp_path, path)
+        .map_err(|_| format!("Failed to move snapshot into place: {}", path.display()))
+}
+
+/// Load a snapshot from `path`, refusing anything written by an incompatible version.
+pub fn load(path: &Path) -> Result<FTSIndex, String> {
+    let file = File::open(path)
+        .map_err(|_| format!("Failed to open snapshot file: {}", path.display()))?;
+    let mut reader = BufReader::new(file);
+
+    let header: Header = bincode::deserialize_from(&mut reader)
+        .map_err(|err| format!("Failed to read snapshot header: {}", err))?;
+    if header.magic != MAGIC {
+        return Err(format!("Not a snapshot file: {}", path.display()));
+    }
+
+    if header.version != SNAPSHOT_VERSION {
+        return Err(format!(
+            "Incompatible snapshot version {} (expected {})",
+            header.version, SNAPSHOT_VERSION
+        ));
+    }
+
+    let mut index: FTSIndex = bincode::deserialize_from(&mut reader)
+        .map_err(|err| format!("Failed to read snapshot: {}", err))?;
+    index.rebuild_lookup_tables();
+
+    Ok(index)
+}
+
+/// Whether an index built from manifests with the given modification times is
+/// still current, given a fresh load of the manifests. Any manifest that
+/// changed, appeared, or disappeared since makes it stale.
+pub fn is_fresh(
+    last_modified: &HashMap<String, SystemTime>,
+    manifests: &[Result<Manifest, ManifestError>],
+) -> bool {
+    let mut unchanged = HashSet::new();
+    for manifest in manifests {
+        match *manifest {
+            Ok(ref manifest) if manifest.body.is_none() => {
+                unchanged.insert(&manifest.search_property);
+            }
+            Ok(_) => return false,
+            Err(_) => (),
+        }
+    }
+
+    unchanged.len() == last_modified.len() && last_modified.keys().all(|p| unchanged.contains(p))
+}
+
+/// (De)serialize a time::Timespec as a (seconds, nanoseconds) pair.
+pub mod timespec {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use time::Timespec;
+
+    pub fn serialize<S: Serializer>(ts: &Timespec, serializer: S) -> Result<S::Ok, S::Error> {
+        (ts.sec, ts.nsec).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Timespec, D::Error> {
+        let (sec, nsec) = <(i64, i32)>::deserialize(deserializer)?;
+        Ok(Timespec::new(sec, nsec))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::RankingConfig;
+    use fts::{Field, SearchOptions};
+    use manifest::{ManifestData, ManifestDocument};
+    use query::Query;
+    use std::env;
+
+    fn make_index() -> FTSIndex {
+        let mut index = FTSIndex::new(
+            vec![Field::new("text", 1.0), Field::new("title", 10.0)],
+            RankingConfig::default(),
+        );
+        index.add(
+            ManifestDocument {
+                slug: "Fox".to_owned(),
+                title: "Fox".to_owned(),
+                tags: "".to_owned(),
+                headings: vec![],
+                links: vec![],
+                extra: HashMap::new(),
+                metadata: HashMap::new(),
+                boost: None,
+                text: "Foxes are small-to-medium-sized, omnivorous mammals.".to_owned(),
+                preview: "".to_owned(),
+                url: "https://en.wikipedia.org/wiki/Fox".to_owned(),
+            },
+            true,
+            "property".to_owned(),
+        );
+        index.last_modified = hashmap!["property".to_owned() => SystemTime::now()];
+        index.finish();
+        index
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let path = env::temp_dir().join(format!("marian-snapshot-test-{}", ::std::process::id()));
+        let index = make_index();
+        save(&path, &index).unwrap();
+
+        let loaded = load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.last_modified, index.last_modified);
+        assert_eq!(loaded.finished, index.finished);
+
+        let query = Query::new("fox", &[], &[]);
+        let urls: Vec<_> = loaded
+            .search(&query, &SearchOptions::default())
+            .results
+            .iter()
+            .map(|result| result.document.url.to_owned())
+            .collect();
+        assert_eq!(urls, vec!["https://en.wikipedia.org/wiki/Fox/".to_owned()]);
+        assert_eq!(
+            loaded.spelling_corrections(&Query::new("omnivorus", &[], &[])),
+            hashmap!["omnivorus".to_owned() => "omnivorous".to_owned()]
+        );
+        assert_eq!(loaded.suggest("fo", &[], 10).titles.len(), 1);
+    }
+
+    #[test]
+    fn test_is_fresh() {
+        let unchanged = |search_property: &str| {
+            Ok(Manifest {
+                body: None,
+                last_modified: SystemTime::now(),
+                search_property: search_property.to_owned(),
+            })
+        };
+        let changed = |search_property: &str| {
+            Ok(Manifest {
+                body: Some(ManifestData {
+                    include_in_global_search: true,
+                    aliases: vec![],
+                    boost: None,
+                    documents: vec![],
+                    url: "https://example.com".to_owned(),
+                }),
+                last_modified: SystemTime::now(),
+                search_property: search_property.to_owned(),
+            })
+        };
+
+        let last_modified = hashmap![
+            "atlas".to_owned() => SystemTime::now(),
+            "compass".to_owned() => SystemTime::now(),
+        ];
+        assert!(is_fresh(&last_modified, &[unchanged("atlas"), unchanged("compass")]));
+        assert!(!is_fresh(&last_modified, &[unchanged("atlas"), changed("compass")]));
+        assert!(!is_fresh(&last_modified, &[unchanged("atlas")]));
+
+        // A manifest added since the snapshot was taken must be indexed
+        assert!(!is_fresh(
+            &last_modified,
+            &[unchanged("atlas"), unchanged("compass"), changed("bi-connector")]
+        ));
+        assert!(!is_fresh(
+            &last_modified,
+            &[
+                unchanged("atlas"),
+                Err(ManifestError {
+                    search_property: "compass".to_owned(),
+                    message: "Oops".to_owned(),
+                }),
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_bad_version() {
+        let path =
+            env::temp_dir().join(format!("marian-snapshot-version-{}", ::std::process::id()));
+        {
+            let mut file = File::create(&path).unwrap();
+            let header = Header {
+                magic: MAGIC,
+                version: SNAPSHOT_VERSION + 1,
+            };
+            bincode::serialize_into(&mut file, &header).unwrap();
+        }
+
+        let result = load(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,236 @@
+use std::collections::{BTreeSet, HashSet};
+
+/// The approximate length of a snippet, in bytes.
+const SNIPPET_LENGTH: usize = 200;
+const ELLIPSIS: &str = "…";
+
+/// A window of a document's text around the words that matched a query.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Snippet {
+    pub text: String,
+
+    /// The [start, end) character offsets of each matching word within `text`.
+    pub highlights: Vec<(usize, usize)>,
+}
+
+/// The byte range of a word in a document's text, and the indexed term it
+/// was matched as. Hits must be sorted by `start` and must not overlap.
+#[derive(Debug, PartialEq)]
+pub struct Hit {
+    pub start: usize,
+    pub end: usize,
+    pub term: String,
+}
+
+/// Return the distinct, lowercased words in `text` covered by `hits`.
+pub fn matched_words(text: &str, hits: &[Hit]) -> BTreeSet<String> {
+    hits.iter()
+        .map(|hit| text[hit.start..hit.end].to_lowercase())
+        .collect()
+}
+
+/// Pick the run of hits that fits within a snippet and covers the most
+/// distinct terms, preferring the earliest. Returns a half-open range of hits.
+fn best_window(hits: &[Hit]) -> (usize, usize) {
+    let mut best = (0, 0);
+    let mut best_score = (0, 0);
+
+    for i in 0..hits.len() {
+        // A hit longer than a snippet still gets a window of its own.
+        let mut terms = HashSet::new();
+        terms.insert(&hits[i].term);
+        let mut j = i + 1;
+        while j < hits.len() && hits[j].end - hits[i].start <= SNIPPET_LENGTH {
+            terms.insert(&hits[j].term);
+            j += 1;
+        }
+
+        let score = (terms.len(), j - i);
+        if score > best_score {
+            best_score = score;
+            best = (i, j);
+        }
+    }
+
+    best
+}
+
+fn floor_char_boundary(text: &str, mut i: usize) -> usize {
+    while !text.is_char_boundary(i) {
+        i -= 1;
+    }
+
+    i
+}
+
+/// Build a snippet of `text` around `hits`, or return None if there are none.
+pub fn make_snippet(text: &str, hits: &[Hit]) -> Option<Snippet> {
+    if hits.is_empty() {
+        return None;
+    }
+
+    let (first, last) = best_window(hits);
+    let hits = &hits[first..last];
+    let hits_start = hits[0].start;
+    let hits_end = hits[hits.len() - 1].end;
+
+    // Center the hits within the snippet, then trim partial words from each end.
+    let slack = SNIPPET_LENGTH.saturating_sub(hits_end - hits_start);
+    let mut start = hits_start.saturating_sub(slack / 2);
+    let mut end = start + SNIPPET_LENGTH;
+    if end > text.len() {
+        end = text.len();
+        start = end.saturating_sub(SNIPPET_LENGTH).min(hits_start);
+    }
+
+    start = floor_char_boundary(text, start);
+    end = floor_char_boundary(text, end.max(hits_end));
+
+    if start > 0 {
+        if let Some(i) = text[start..hits_start].find(char::is_whitespace) {
+            start += i;
+        }
+    }
+
+    if end < text.len() {
+        if let Some(i) = text[hits_end..end].rfind(char::is_whitespace) {
+            end = hits_end + i;
+        }
+    }
+
+    let prefix = if start > 0 { ELLIPSIS } else { "" };
+    let suffix = if end < text.len() { ELLIPSIS } else { "" };
+    let body = &text[start..end];
+    let body_start = start + body.find(|c: char| !c.is_whitespace()).unwrap_or(0);
+    let body = body.trim();
+
+    // Collapse runs of whitespace, tracking where each hit lands.
+    let mut snippet_text = String::from(prefix);
+    let mut count = prefix.chars().count();
+    let mut highlights = vec![];
+    let mut hits = hits.iter().peekable();
+    let mut last_was_whitespace = false;
+    for (i, c) in body.char_indices() {
+        if c.is_whitespace() {
+            if last_was_whitespace {
+                continue;
+            }
+
+            last_was_whitespace = true;
+            snippet_text.push(' ');
+        } else {
+            last_was_whitespace = false;
+            if let Some(&hit) = hits.peek() {
+                if body_start + i == hit.start {
+                    let word = &text[hit.start..hit.end];
+                    highlights.push((count, count + word.chars().count()));
+                    hits.next();
+                }
+            }
+
+            snippet_text.push(c);
+        }
+
+        count += 1;
+    }
+
+    snippet_text.push_str(suffix);
+
+    Some(Snippet {
+        text: snippet_text,
+        highlights,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stemmer::{stem, tokenize_spans};
+
+    /// Find the words in `text` whose stems match one of `words`.
+    fn hits(text: &str, words: &[&str]) -> Vec<Hit> {
+        let terms: HashSet<_> = words.iter().map(|w| stem(w)).collect();
+        let mut hits: Vec<_> = tokenize_spans(text, true)
+            .into_iter()
+            .map(|(token, (start, end))| Hit {
+                start,
+                end,
+                term: stem(&token),
+            })
+            .filter(|hit| terms.contains(&hit.term))
+            .collect();
+        hits.dedup_by_key(|hit| hit.start);
+        hits
+    }
+
+    fn highlighted(snippet: &Snippet) -> Vec<String> {
+        let chars: Vec<_> = snippet.text.chars().collect();
+        snippet
+            .highlights
+            .iter()
+            .map(|&(start, end)| chars[start..end].iter().collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_short_text() {
+        let snippet = make_snippet(
+            "The Connect dialog provides the details to connect via Compass.",
+            &hits(
+                "The Connect dialog provides the details to connect via Compass.",
+                &["connect", "compass"],
+            ),
+        ).unwrap();
+        assert_eq!(
+            snippet.text,
+            "The Connect dialog provides the details to connect via Compass."
+        );
+        assert_eq!(snippet.highlights, vec![(4, 11), (43, 50), (55, 62)]);
+        assert_eq!(highlighted(&snippet), vec!["Connect", "connect", "Compass"]);
+    }
+
+    #[test]
+    fn test_window() {
+        let text = format!(
+            "{} Foxes are omnivorous mammals. {} The red fox is the largest of the true foxes. {}",
+            "Lorem ipsum dolor sit amet. ".repeat(10),
+            "Sed ut perspiciatis unde omnis. ".repeat(10),
+            "Ut enim ad minima veniam. ".repeat(10)
+        );
+
+        // Prefer the window covering the most distinct terms
+        let snippet = make_snippet(&text, &hits(&text, &["red", "fox"])).unwrap();
+        assert!(snippet.text.starts_with(ELLIPSIS));
+        assert!(snippet.text.ends_with(ELLIPSIS));
+        assert!(snippet.text.len() <= SNIPPET_LENGTH + 2 * ELLIPSIS.len());
+        assert_eq!(highlighted(&snippet), vec!["red", "fox", "foxes"]);
+
+        assert_eq!(make_snippet(&text, &hits(&text, &["badger"])), None);
+    }
+
+    #[test]
+    fn test_multibyte() {
+        let text = "Omnivore /ˈɒmnivɔər/ —\n\n  an omnivore eats plants";
+        let snippet = make_snippet(text, &hits(text, &["plant"])).unwrap();
+        assert_eq!(snippet.text, "Omnivore /ˈɒmnivɔər/ — an omnivore eats plants");
+        assert_eq!(highlighted(&snippet), vec!["plants"]);
+    }
+
+    #[test]
+    fn test_long_word() {
+        // A single hit longer than a snippet is returned whole
+        let word = "a.".repeat(SNIPPET_LENGTH) + "find";
+        let text = format!("Call {} to look up documents", word);
+        let snippet = make_snippet(&text, &hits(&text, &["find"])).unwrap();
+        assert_eq!(highlighted(&snippet), vec![word]);
+    }
+
+    #[test]
+    fn test_matched_words() {
+        let text = "Use db.collection.find() to find documents";
+        assert_eq!(
+            matched_words(text, &hits(text, &["find"])),
+            btreeset!["db.collection.find".to_owned(), "find".to_owned()]
+        );
+    }
+}
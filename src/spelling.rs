@@ -0,0 +1,204 @@
+use std::collections::{HashMap, HashSet};
+use std::{cmp, mem};
+
+/// Words shorter than this are never offered as, or corrected to, a suggestion.
+const MIN_WORD_LENGTH: usize = 3;
+
+/// Return the Levenshtein distance between `a` and `b`, or None if it exceeds
+/// `max_distance`.
+pub fn levenshtein(a: &str, b: &str, max_distance: usize) -> Option<usize> {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+
+    let length_difference = if a.len() > b.len() {
+        a.len() - b.len()
+    } else {
+        b.len() - a.len()
+    };
+    if length_difference > max_distance {
+        return None;
+    }
+
+    let mut previous_row: Vec<usize> = (0..b.len() + 1).collect();
+    let mut current_row = vec![0; b.len() + 1];
+
+    for (i, a_char) in a.iter().enumerate() {
+        current_row[0] = i + 1;
+        let mut row_minimum = current_row[0];
+
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution_cost = if a_char == b_char { 0 } else { 1 };
+            current_row[j + 1] = cmp::min(
+                cmp::min(previous_row[j + 1] + 1, current_row[j] + 1),
+                previous_row[j] + substitution_cost,
+            );
+            row_minimum = cmp::min(row_minimum, current_row[j + 1]);
+        }
+
+        // No cell in this row is within bounds, so no later row can be either.
+        if row_minimum > max_distance {
+            return None;
+        }
+
+        mem::swap(&mut previous_row, &mut current_row);
+    }
+
+    let distance = previous_row[b.len()];
+    if distance <= max_distance {
+        Some(distance)
+    } else {
+        None
+    }
+}
+
+/// Return the padded character trigrams of a word: "fox" yields "$fo", "fox", "ox$".
+fn trigrams(word: &str) -> Vec<String> {
+    let mut padded = vec!['$'];
+    padded.extend(word.chars());
+    padded.push('$');
+
+    padded.windows(3).map(|w| w.iter().collect()).collect()
+}
+
+/// The maximum number of edits we'll tolerate when correcting a word of a given length.
+pub fn max_edits(word: &str) -> usize {
+    if word.chars().count() <= 4 {
+        1
+    } else {
+        2
+    }
+}
+
+/// Only plain alphabetic words are worth suggesting; code tokens like `$gte` or
+/// `db.collection.find` are too easy to mangle.
+pub fn is_correctable(word: &str) -> bool {
+    word.len() >= MIN_WORD_LENGTH && word.chars().all(|c| c.is_alphabetic())
+}
+
+/// A vocabulary of unstemmed words and their frequencies, indexed by trigram
+/// so that likely corrections for a misspelled word can be found quickly.
+#[derive(Serialize, Deserialize)]
+pub struct Speller {
+    words: Vec<(String, u32)>,
+    word_ids: HashMap<String, usize>,
+    #[serde(skip)]
+    trigrams: HashMap<String, Vec<usize>>,
+}
+
+impl Speller {
+    pub fn new() -> Self {
+        Self {
+            words: vec![],
+            word_ids: HashMap::new(),
+            trigrams: HashMap::new(),
+        }
+    }
+
+    /// Record `count` occurrences of `word` in the corpus.
+    pub fn insert(&mut self, word: &str, count: u32) {
+        if !is_correctable(word) {
+            return;
+        }
+
+        if let Some(&id) = self.word_ids.get(word) {
+            self.words[id].1 += count;
+            return;
+        }
+
+        let id = self.words.len();
+        self.words.push((word.to_owned(), count));
+        self.word_ids.insert(word.to_owned(), id);
+    }
+
+    /// Build the trigram index. Must be called after all words have been inserted.
+    pub fn finish(&mut self) {
+        self.trigrams.clear();
+        for (id, entry) in self.words.iter().enumerate() {
+            for trigram in trigrams(&entry.0) {
+                let entry = self.trigrams.entry(trigram).or_insert_with(Vec::new);
+                if entry.last() != Some(&id) {
+                    entry.push(id);
+                }
+            }
+        }
+    }
+
+    /// Return the most likely intended spelling of `word`, or None if there is no
+    /// plausible candidate. Candidates are words sharing at least one trigram with
+    /// the input; among those within the edit budget, the closest wins, with ties
+    /// broken by corpus frequency.
+    pub fn correct(&self, word: &str) -> Option<&str> {
+        if !is_correctable(word) || self.word_ids.contains_key(word) {
+            return None;
+        }
+
+        let mut candidates: HashSet<usize> = HashSet::new();
+        for trigram in trigrams(word) {
+            if let Some(ids) = self.trigrams.get(&trigram) {
+                candidates.extend(ids);
+            }
+        }
+
+        let max_distance = max_edits(word);
+        let mut best: Option<(usize, u32, &str)> = None;
+        for &id in &candidates {
+            let (ref candidate, frequency) = self.words[id];
+            let distance = match levenshtein(word, candidate, max_distance) {
+                Some(d) => d,
+                None => continue,
+            };
+
+            let is_better = match best {
+                None => true,
+                Some((best_distance, best_frequency, best_word)) => {
+                    (distance, cmp::Reverse(frequency), candidate.as_str())
+                        < (best_distance, cmp::Reverse(best_frequency), best_word)
+                }
+            };
+
+            if is_better {
+                best = Some((distance, frequency, candidate));
+            }
+        }
+
+        best.map(|(_, _, word)| word)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_levenshtein() {
+        assert_eq!(levenshtein("quary", "query", 2), Some(1));
+        assert_eq!(levenshtein("kitten", "sitting", 3), Some(3));
+        assert_eq!(levenshtein("kitten", "sitting", 2), None);
+        assert_eq!(levenshtein("", "abc", 3), Some(3));
+        assert_eq!(levenshtein("same", "same", 0), Some(0));
+    }
+
+    #[test]
+    fn test_correct() {
+        let mut speller = Speller::new();
+        for &(word, count) in &[
+            ("query", 3),
+            ("quart", 1),
+            ("replica", 1),
+            ("$gte", 1),
+            ("db", 1),
+        ] {
+            speller.insert(word, count);
+        }
+        speller.finish();
+
+        // Ties in distance should be broken by frequency
+        assert_eq!(speller.correct("quary"), Some("query"));
+        assert_eq!(speller.correct("replicaa"), Some("replica"));
+
+        // Known and uncorrectable words should be left alone
+        assert_eq!(speller.correct("query"), None);
+        assert_eq!(speller.correct("$gtee"), None);
+        assert_eq!(speller.correct("xylophone"), None);
+    }
+}
@@ -12,8 +12,6 @@ use std::collections::{HashMap, HashSet};
 lazy_static! {
     static ref PAT_TOKEN_SEPARATOR: Regex =
         Regex::new(r#"[^\w$%.]+"#).expect("Failed to compile token separator regex");
-    static ref PAT_BAD_CHARS: Regex =
-        Regex::new(r#"(?:^\.)|(?:\.$)"#).expect("Failed to compile bad char regex");
     static ref STOP_WORDS: HashSet<&'static str> = hashset![
         "a",
         "able",
@@ -168,11 +166,37 @@ pub fn stem(word: &str) -> String {
     })
 }
 
+/// A piece of text between token separators, with one leading and one trailing
+/// period removed, and where in the text it came from.
+fn component(text: &str, mut start: usize, mut end: usize) -> (String, (usize, usize)) {
+    if text[start..end].starts_with('.') {
+        start += 1;
+    }
+
+    if text[start..end].ends_with('.') {
+        end -= 1;
+    }
+
+    (text[start..end].to_lowercase(), (start, end))
+}
+
 pub fn tokenize(text: &str, fuzzy: bool) -> Vec<String> {
-    let components: Vec<_> = PAT_TOKEN_SEPARATOR
-        .split(text)
-        .map(|token| PAT_BAD_CHARS.replace_all(token, "").to_lowercase())
-        .collect();
+    tokenize_spans(text, fuzzy)
+        .into_iter()
+        .map(|(token, _)| token)
+        .collect()
+}
+
+/// Tokenize text as `tokenize` does, along with the byte range of the text
+/// that each token came from.
+pub fn tokenize_spans(text: &str, fuzzy: bool) -> Vec<(String, (usize, usize))> {
+    let mut components = vec![];
+    let mut start = 0;
+    for separator in PAT_TOKEN_SEPARATOR.find_iter(text) {
+        components.push(component(text, start, separator.start()));
+        start = separator.end();
+    }
+    components.push(component(text, start, text.len()));
 
     let mut skip = false;
     let mut tokens = Vec::with_capacity(components.len());
@@ -182,30 +206,31 @@ pub fn tokenize(text: &str, fuzzy: bool) -> Vec<String> {
             continue;
         }
 
-        let token: &str = &components[i];
+        let (ref token, span) = components[i];
         if token == "$" {
-            tokens.push("positional".to_owned());
-            tokens.push("operator".to_owned());
+            tokens.push(("positional".to_owned(), span));
+            tokens.push(("operator".to_owned(), span));
             continue;
         }
 
-        if let Some(next_token) = components.get(i + 1) {
-            let atomic_phrase_option: Option<&str> = ATOMIC_PHRASE_MAP.get(token).cloned();
-            if atomic_phrase_option == Some(next_token) {
-                tokens.push(format!("{} {}", token, ATOMIC_PHRASE_MAP[token]));
+        if let Some(&(ref next_token, next_span)) = components.get(i + 1) {
+            let atomic_phrase_option: Option<&str> = ATOMIC_PHRASE_MAP.get(token.as_str()).cloned();
+            if atomic_phrase_option == Some(next_token.as_str()) {
+                let phrase = format!("{} {}", token, ATOMIC_PHRASE_MAP[token.as_str()]);
+                tokens.push((phrase, (span.0, next_span.1)));
                 skip = true;
                 continue;
             }
         }
 
         if token.len() > 1 {
-            tokens.push(token.to_owned());
+            tokens.push((token.to_owned(), span));
         }
 
         if fuzzy {
             for subtoken in token.split('.') {
                 if subtoken.len() > 1 {
-                    tokens.push(subtoken.to_owned());
+                    tokens.push((subtoken.to_owned(), span));
                 }
             }
         }
@@ -248,6 +273,28 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_tokenize_spans() {
+        let text = "Call .db.Find() in ops manager";
+        let spans: Vec<_> = tokenize_spans(text, true)
+            .into_iter()
+            .map(|(token, (start, end))| (token, &text[start..end]))
+            .collect();
+        assert_eq!(
+            spans,
+            vec![
+                ("call".to_owned(), "Call"),
+                ("call".to_owned(), "Call"),
+                ("db.find".to_owned(), "db.Find"),
+                ("db".to_owned(), "db.Find"),
+                ("find".to_owned(), "db.Find"),
+                ("in".to_owned(), "in"),
+                ("in".to_owned(), "in"),
+                ("ops manager".to_owned(), "ops manager"),
+            ]
+        );
+    }
+
     #[test]
     fn test_atomic_phrases() {
         assert_eq!(
@@ -1,5 +1,6 @@
 use fts::DocID;
 use qp_trie;
+use spelling::levenshtein;
 use std::collections::{HashMap, HashSet};
 
 pub struct Trie {
@@ -18,6 +19,11 @@ impl Trie {
         self.trie.entry(key).or_insert_with(HashSet::new).insert(id);
     }
 
+    /// Return true if any token begins with `prefix`.
+    pub fn has_prefix(&self, prefix: &str) -> bool {
+        self.trie.iter_prefix_str(prefix).next().is_some()
+    }
+
     pub fn search(&self, term: &str) -> HashMap<DocID, Vec<&str>> {
         let mut result = HashMap::new();
 
@@ -32,6 +38,83 @@ impl Trie {
 
         result
     }
+
+    /// Return the documents containing exactly `token`.
+    pub fn get(&self, token: &str) -> Option<&HashSet<DocID>> {
+        self.trie.get_str(token)
+    }
+
+    /// Return each token beginning with `prefix`, and the documents containing it.
+    pub fn tokens_with_prefix(&self, prefix: &str) -> Vec<(&str, &HashSet<DocID>)> {
+        self.trie
+            .iter_prefix_str(prefix)
+            .map(|(k, doc_ids)| (k.as_str(), doc_ids))
+            .collect()
+    }
+
+    /// Like `search`, but matching at most `max_expansions` tokens other than
+    /// `term` itself, preferring those found in the most documents.
+    pub fn search_expanded(&self, term: &str, max_expansions: usize) -> HashMap<DocID, Vec<&str>> {
+        let mut exact = None;
+        let mut expansions = vec![];
+        for (k, doc_ids) in self.trie.iter_prefix_str(term) {
+            if k.as_str() == term {
+                exact = Some((k, doc_ids));
+            } else {
+                expansions.push((k, doc_ids));
+            }
+        }
+
+        expansions.sort_by(|a, b| b.1.len().cmp(&a.1.len()).then_with(|| a.0.cmp(b.0)));
+        expansions.truncate(max_expansions);
+
+        let mut result = HashMap::new();
+        for (k, doc_ids) in exact.into_iter().chain(expansions) {
+            for &doc_id in doc_ids {
+                result
+                    .entry(doc_id)
+                    .or_insert_with(Vec::new)
+                    .push(k.as_str());
+            }
+        }
+
+        result
+    }
+
+    /// Return the tokens within `max_distance` edits of `term`, other than
+    /// those beginning with it, along with their distance. Typos rarely
+    /// involve the first character, so only tokens sharing it are considered,
+    /// and of those, only the first `max_candidates` of a plausible length.
+    pub fn fuzzy_search(
+        &self,
+        term: &str,
+        max_distance: usize,
+        max_candidates: usize,
+    ) -> Vec<(&str, usize)> {
+        let first = match term.chars().next() {
+            Some(c) => c.len_utf8(),
+            None => return vec![],
+        };
+
+        let length = term.chars().count();
+        self.trie
+            .iter_prefix_str(&term[..first])
+            .map(|(k, _)| k.as_str())
+            .filter(|token| !token.starts_with(term))
+            .filter(|token| {
+                let token_length = token.chars().count();
+                token_length + max_distance >= length && token_length <= length + max_distance
+            })
+            .take(max_candidates)
+            .filter_map(|token| levenshtein(term, token, max_distance).map(|d| (token, d)))
+            .collect()
+    }
+}
+
+impl Default for Trie {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[cfg(test)]
@@ -78,4 +161,62 @@ mod tests {
                 DocID(1) => vec!["foobar"]]
         );
     }
+
+    #[test]
+    fn test_search_expanded() {
+        let mut trie = Trie::new();
+        trie.insert("foo", DocID(0));
+        trie.insert("foobar", DocID(1));
+        trie.insert("foobaz", DocID(1));
+        trie.insert("foobaz", DocID(2));
+
+        assert_eq!(trie.search_expanded("foo", 0), hashmap![DocID(0) => vec!["foo"]]);
+        assert_eq!(
+            trie.search_expanded("foo", 1),
+            hashmap![
+                DocID(0) => vec!["foo"],
+                DocID(1) => vec!["foobaz"],
+                DocID(2) => vec!["foobaz"]]
+        );
+        assert_eq!(
+            trie.search_expanded("foo", 5),
+            hashmap![
+                DocID(0) => vec!["foo"],
+                DocID(1) => vec!["foobaz", "foobar"],
+                DocID(2) => vec!["foobaz"]]
+        );
+        assert!(trie.search_expanded("fo", 0).is_empty());
+    }
+
+    #[test]
+    fn test_fuzzy_search() {
+        let mut trie = Trie::new();
+        trie.insert("query", DocID(0));
+        trie.insert("queries", DocID(0));
+        trie.insert("quarry", DocID(1));
+        trie.insert("jquery", DocID(1));
+
+        let mut matches = trie.fuzzy_search("quary", 1, 100);
+        matches.sort();
+        assert_eq!(matches, vec![("quarry", 1), ("query", 1)]);
+
+        // Tokens the term is a prefix of are already matched exactly
+        assert_eq!(trie.fuzzy_search("quer", 2, 100), vec![]);
+        assert_eq!(trie.fuzzy_search("", 2, 100), vec![]);
+
+        // Tokens too long or short to match don't count toward the limit
+        assert_eq!(trie.fuzzy_search("quary", 1, 1), vec![("quarry", 1)]);
+        assert_eq!(trie.fuzzy_search("quary", 1, 0), vec![]);
+    }
+
+    #[test]
+    fn test_has_prefix() {
+        let mut trie = Trie::new();
+        trie.insert("foobar", DocID(0));
+
+        assert!(trie.has_prefix("foo"));
+        assert!(trie.has_prefix("foobar"));
+        assert!(!trie.has_prefix("foobarbaz"));
+        assert!(!trie.has_prefix("bar"));
+    }
 }
@@ -13,7 +13,7 @@ describe('integration', function() {
     let ctx = null
 
     before('starting server', function(done) {
-        ctx = testUtil.startServer('target/debug/marian-rust', 'dir:test/manifests/', done)
+        ctx = testUtil.startServer('target/debug/marian-rust', ['--pinned', 'test/pinned.toml', 'dir:test/manifests/'], done)
     })
 
     let lastSync
@@ -31,7 +31,7 @@ describe('integration', function() {
             const result = await testUtil.request(`${ctx.host}/search?q=${encodeURIComponent('"connect dialog" compass')}`)
             assert.strictEqual(result.response.statusCode, 200)
             assert.strictEqual(result.response.headers['content-type'], 'application/json')
-            assert.deepStrictEqual(result.json, {'results':[{'title':'Connect via Compass — MongoDB Atlas','preview':'The Connect dialog for a cluster provides the details to connect to a cluster via Compass.','url':'https://docs.atlas.mongodb.com/compass-connection/'},{'title':'Connect via Driver — MongoDB Atlas','preview':'The Connect dialog for a cluster provides the details to connect to a cluster with an application using a MongoDB driver.','url':'https://docs.atlas.mongodb.com/driver-connection/'},{'title':'Connect via mongo Shell — MongoDB Atlas','preview':'The Connect dialog for a cluster provides the details to connect to a cluster via the mongo shell.','url':'https://docs.atlas.mongodb.com/mongo-shell-connection/'},{'title':'Connect to a Cluster — MongoDB Atlas','preview':'Atlas provides instructions on connecting to a cluster via the mongo shell, a MongoDB driver, or MongoDB Compass via the Atlas UI.','url':'https://docs.atlas.mongodb.com/connect-to-cluster/'},{'title':'Set up VPC Peering Connection — MongoDB Atlas','preview':'For Atlas clusters deployed on Google Cloud Platform or Microsoft Azure, add the IP addresses of your GCP or Azure services to Atlas group IP whitelist to grant those services access to the cluster.','url':'https://docs.atlas.mongodb.com/security-vpc-peering/'},{'title':'Connect from Tableau Desktop — MongoDB Connector for BI 2.2','preview':'The MongoDB Connector for BI is a named connector in Tableau.','url':'https://docs.mongodb.com/bi-connector/current/connect/tableau/'},{'title':'Load File with mongoimport — MongoDB Atlas','preview':'You can use mongoimport to import data from a JSON or a CSV file into MongoDB Atlas cluster.','url':'https://docs.atlas.mongodb.com/import/mongoimport/'},{'title':'Migrate with mongomirror — MongoDB Atlas','preview':'mongomirror is a utility for migrating data from an existing MongoDB replica set to a MongoDB Atlas replica set. mongomirror does not require you to shut down your existing replica set or applications.','url':'https://docs.atlas.mongodb.com/import/mongomirror/'},{'title':'MongoDB Atlas — MongoDB Atlas','preview':'MongoDB Atlas is a cloud service for running, monitoring, and maintaining MongoDB deployments, including the provisioning of dedicated servers for the MongoDB instances. In addition, Atlas provides the ability to introspect collections, query backups, and migrate data from existing MongoDB replica set into an Atlas cluster.','url':'https://docs.atlas.mongodb.com/'}],'spellingCorrections':{}})
+            assert.deepStrictEqual(result.json, {'results':[{'title':'Connect via Compass — MongoDB Atlas','preview':'The Connect dialog for a cluster provides the details to connect to a cluster via Compass.','url':'https://docs.atlas.mongodb.com/compass-connection/'},{'title':'Connect via Driver — MongoDB Atlas','preview':'The Connect dialog for a cluster provides the details to connect to a cluster with an application using a MongoDB driver.','url':'https://docs.atlas.mongodb.com/driver-connection/'},{'title':'Connect via mongo Shell — MongoDB Atlas','preview':'The Connect dialog for a cluster provides the details to connect to a cluster via the mongo shell.','url':'https://docs.atlas.mongodb.com/mongo-shell-connection/'},{'title':'Connect to a Cluster — MongoDB Atlas','preview':'Atlas provides instructions on connecting to a cluster via the mongo shell, a MongoDB driver, or MongoDB Compass via the Atlas UI.','url':'https://docs.atlas.mongodb.com/connect-to-cluster/'},{'title':'Set up VPC Peering Connection — MongoDB Atlas','preview':'For Atlas clusters deployed on Google Cloud Platform or Microsoft Azure, add the IP addresses of your GCP or Azure services to Atlas group IP whitelist to grant those services access to the cluster.','url':'https://docs.atlas.mongodb.com/security-vpc-peering/'},{'title':'Connect from Tableau Desktop — MongoDB Connector for BI 2.2','preview':'The MongoDB Connector for BI is a named connector in Tableau.','url':'https://docs.mongodb.com/bi-connector/current/connect/tableau/'},{'title':'Load File with mongoimport — MongoDB Atlas','preview':'You can use mongoimport to import data from a JSON or a CSV file into MongoDB Atlas cluster.','url':'https://docs.atlas.mongodb.com/import/mongoimport/'},{'title':'Migrate with mongomirror — MongoDB Atlas','preview':'mongomirror is a utility for migrating data from an existing MongoDB replica set to a MongoDB Atlas replica set. mongomirror does not require you to shut down your existing replica set or applications.','url':'https://docs.atlas.mongodb.com/import/mongomirror/'},{'title':'MongoDB Atlas — MongoDB Atlas','preview':'MongoDB Atlas is a cloud service for running, monitoring, and maintaining MongoDB deployments, including the provisioning of dedicated servers for the MongoDB instances. In addition, Atlas provides the ability to introspect collections, query backups, and migrate data from existing MongoDB replica set into an Atlas cluster.','url':'https://docs.atlas.mongodb.com/'}],'total':9,'spellingCorrections':{}})
         })
 
         // Test variants of searchProperty
@@ -39,7 +39,7 @@ describe('integration', function() {
             let result = await testUtil.request(`${ctx.host}/search?q=aggregation`)
             assert.strictEqual(result.response.statusCode, 200)
             assert.strictEqual(result.response.headers['content-type'], 'application/json')
-            assert.deepStrictEqual(result.json, {'results': [{"title":"Schema Configuration — MongoDB Connector for BI 2.2","preview":"Business intelligence tools connect to a data source and, given a fixed tabular schema, allow the user to visually explore their data. As MongoDB uses a flexible schema, these tools currently cannot use MongoDB as a native data source.","url":"https://docs.mongodb.com/bi-connector/current/schema-configuration/"},{"title":"Supported SQL Functions and Operators — MongoDB Connector for BI 2.2","preview":"MongoDB Connector for BI Version 2.2 is compatible with SQL-99 SELECT statements.","url":"https://docs.mongodb.com/bi-connector/current/supported-operations/"},{"title":"MongoDB Reference — MongoDB Atlas","preview":"For a comprehensive documentation of MongoDB, refer to the MongoDB Manual. The following sections in the manual provide some starting points for developing with MongoDB.","url":"https://docs.atlas.mongodb.com/mongodb-reference/"},{"title":"Command Limitations in Free Tier Clusters — MongoDB Atlas","preview":"Atlas Free Tier clusters do not support all functionality available to other clusters.","url":"https://docs.atlas.mongodb.com/unsupported-commands/"},{"title":"Monitor a Cluster — MongoDB Atlas","preview":"Atlas collects and displays metrics for your servers, databases, and MongoDB processes. Atlas displays three charts in the Clusters view and additional charts in the Metrics view.","url":"https://docs.atlas.mongodb.com/monitor-cluster-metrics/"},{"title":"Create a Cluster — MongoDB Atlas","preview":"Atlas-managed MongoDB deployments, or “clusters”, can be either a replica set or a sharded cluster.","url":"https://docs.atlas.mongodb.com/create-new-cluster/"},{"title":"Query a Backup Snapshot — MongoDB Atlas","preview":"Atlas provides queryable backups. This functionality allows you to query specific backup snapshot. You can use the queryable backups to:","url":"https://docs.atlas.mongodb.com/query-backup/"},{"title":"MongoDB Connector for BI — MongoDB Connector for BI 2.2","preview":"The MongoDB Connector for Business Intelligence (BI) allows users to visualize their MongoDB Enterprise data using existing relational business intelligence tools such as Tableau, MicroStrategy, and Qlik. These tools expect to connect to a data source and find data in tabular form following a fixed schema. This presents a challenge when working with MongoDB’s flexible schema and rich, multi-dimensional documents.","url":"https://docs.mongodb.com/bi-connector/current/"},{"title":"Release Notes for MongoDB Connector for BI — MongoDB Connector for BI 2.2","preview":"Supports authenticating directly against MongoDB using the new C and JDBC authentication plugins. These plugins support SCRAM-SHA-1 and PLAIN mechanisms and remove the SSL requirement for authentication. The authentication plugins can be found on GitHub:","url":"https://docs.mongodb.com/bi-connector/current/release-notes/"},{"title":"mongodrdl — MongoDB Connector for BI 2.2","preview":"The mongodrdl command man page.","url":"https://docs.mongodb.com/bi-connector/current/reference/mongodrdl/"}], 'spellingCorrections': {}})
+            assert.deepStrictEqual(result.json, {'results': [{"title":"Schema Configuration — MongoDB Connector for BI 2.2","preview":"Business intelligence tools connect to a data source and, given a fixed tabular schema, allow the user to visually explore their data. As MongoDB uses a flexible schema, these tools currently cannot use MongoDB as a native data source.","url":"https://docs.mongodb.com/bi-connector/current/schema-configuration/"},{"title":"Supported SQL Functions and Operators — MongoDB Connector for BI 2.2","preview":"MongoDB Connector for BI Version 2.2 is compatible with SQL-99 SELECT statements.","url":"https://docs.mongodb.com/bi-connector/current/supported-operations/"},{"title":"MongoDB Reference — MongoDB Atlas","preview":"For a comprehensive documentation of MongoDB, refer to the MongoDB Manual. The following sections in the manual provide some starting points for developing with MongoDB.","url":"https://docs.atlas.mongodb.com/mongodb-reference/"},{"title":"Command Limitations in Free Tier Clusters — MongoDB Atlas","preview":"Atlas Free Tier clusters do not support all functionality available to other clusters.","url":"https://docs.atlas.mongodb.com/unsupported-commands/"},{"title":"Monitor a Cluster — MongoDB Atlas","preview":"Atlas collects and displays metrics for your servers, databases, and MongoDB processes. Atlas displays three charts in the Clusters view and additional charts in the Metrics view.","url":"https://docs.atlas.mongodb.com/monitor-cluster-metrics/"},{"title":"Create a Cluster — MongoDB Atlas","preview":"Atlas-managed MongoDB deployments, or “clusters”, can be either a replica set or a sharded cluster.","url":"https://docs.atlas.mongodb.com/create-new-cluster/"},{"title":"Query a Backup Snapshot — MongoDB Atlas","preview":"Atlas provides queryable backups. This functionality allows you to query specific backup snapshot. You can use the queryable backups to:","url":"https://docs.atlas.mongodb.com/query-backup/"},{"title":"MongoDB Connector for BI — MongoDB Connector for BI 2.2","preview":"The MongoDB Connector for Business Intelligence (BI) allows users to visualize their MongoDB Enterprise data using existing relational business intelligence tools such as Tableau, MicroStrategy, and Qlik. These tools expect to connect to a data source and find data in tabular form following a fixed schema. This presents a challenge when working with MongoDB’s flexible schema and rich, multi-dimensional documents.","url":"https://docs.mongodb.com/bi-connector/current/"},{"title":"Release Notes for MongoDB Connector for BI — MongoDB Connector for BI 2.2","preview":"Supports authenticating directly against MongoDB using the new C and JDBC authentication plugins. These plugins support SCRAM-SHA-1 and PLAIN mechanisms and remove the SSL requirement for authentication. The authentication plugins can be found on GitHub:","url":"https://docs.mongodb.com/bi-connector/current/release-notes/"},{"title":"mongodrdl — MongoDB Connector for BI 2.2","preview":"The mongodrdl command man page.","url":"https://docs.mongodb.com/bi-connector/current/reference/mongodrdl/"}], 'total': 10, 'spellingCorrections': {}})
 
             const result2 = await testUtil.request(`${ctx.host}/search?q=aggregation&searchProperty=atlas-master,bi-connector-master`)
             assert.deepStrictEqual(result.json, result2.json)
@@ -47,12 +47,209 @@ describe('integration', function() {
             result = await testUtil.request(`${ctx.host}/search?q=aggregation&searchProperty=bi-connector-master`)
             assert.strictEqual(result.response.statusCode, 200)
             assert.strictEqual(result.response.headers['content-type'], 'application/json')
-            assert.deepStrictEqual(result.json, {'results':[{"title":"Schema Configuration — MongoDB Connector for BI 2.2","preview":"Business intelligence tools connect to a data source and, given a fixed tabular schema, allow the user to visually explore their data. As MongoDB uses a flexible schema, these tools currently cannot use MongoDB as a native data source.","url":"https://docs.mongodb.com/bi-connector/current/schema-configuration/"},{"title":"Supported SQL Functions and Operators — MongoDB Connector for BI 2.2","preview":"MongoDB Connector for BI Version 2.2 is compatible with SQL-99 SELECT statements.","url":"https://docs.mongodb.com/bi-connector/current/supported-operations/"},{"title":"MongoDB Connector for BI — MongoDB Connector for BI 2.2","preview":"The MongoDB Connector for Business Intelligence (BI) allows users to visualize their MongoDB Enterprise data using existing relational business intelligence tools such as Tableau, MicroStrategy, and Qlik. These tools expect to connect to a data source and find data in tabular form following a fixed schema. This presents a challenge when working with MongoDB’s flexible schema and rich, multi-dimensional documents.","url":"https://docs.mongodb.com/bi-connector/current/"},{"title":"Release Notes for MongoDB Connector for BI — MongoDB Connector for BI 2.2","preview":"Supports authenticating directly against MongoDB using the new C and JDBC authentication plugins. These plugins support SCRAM-SHA-1 and PLAIN mechanisms and remove the SSL requirement for authentication. The authentication plugins can be found on GitHub:","url":"https://docs.mongodb.com/bi-connector/current/release-notes/"},{"title":"mongodrdl — MongoDB Connector for BI 2.2","preview":"The mongodrdl command man page.","url":"https://docs.mongodb.com/bi-connector/current/reference/mongodrdl/"}],'spellingCorrections':{}})
+            assert.deepStrictEqual(result.json, {'results':[{"title":"Schema Configuration — MongoDB Connector for BI 2.2","preview":"Business intelligence tools connect to a data source and, given a fixed tabular schema, allow the user to visually explore their data. As MongoDB uses a flexible schema, these tools currently cannot use MongoDB as a native data source.","url":"https://docs.mongodb.com/bi-connector/current/schema-configuration/"},{"title":"Supported SQL Functions and Operators — MongoDB Connector for BI 2.2","preview":"MongoDB Connector for BI Version 2.2 is compatible with SQL-99 SELECT statements.","url":"https://docs.mongodb.com/bi-connector/current/supported-operations/"},{"title":"MongoDB Connector for BI — MongoDB Connector for BI 2.2","preview":"The MongoDB Connector for Business Intelligence (BI) allows users to visualize their MongoDB Enterprise data using existing relational business intelligence tools such as Tableau, MicroStrategy, and Qlik. These tools expect to connect to a data source and find data in tabular form following a fixed schema. This presents a challenge when working with MongoDB’s flexible schema and rich, multi-dimensional documents.","url":"https://docs.mongodb.com/bi-connector/current/"},{"title":"Release Notes for MongoDB Connector for BI — MongoDB Connector for BI 2.2","preview":"Supports authenticating directly against MongoDB using the new C and JDBC authentication plugins. These plugins support SCRAM-SHA-1 and PLAIN mechanisms and remove the SSL requirement for authentication. The authentication plugins can be found on GitHub:","url":"https://docs.mongodb.com/bi-connector/current/release-notes/"},{"title":"mongodrdl — MongoDB Connector for BI 2.2","preview":"The mongodrdl command man page.","url":"https://docs.mongodb.com/bi-connector/current/reference/mongodrdl/"}],'total':5,'spellingCorrections':{}})
 
             const result3 = await testUtil.request(`${ctx.host}/search?q=aggregation&searchProperty=bi-connector-alias`)
             assert.deepStrictEqual(result.json, result3.json)
         })
 
+        it('should page through results', async () => {
+            const all = await testUtil.request(`${ctx.host}/search?q=connect`)
+            assert.strictEqual(all.json.total, all.json.results.length)
+
+            const urls = []
+            for (let offset = 0; offset < all.json.total; offset += 4) {
+                const page = await testUtil.request(`${ctx.host}/search?q=connect&offset=${offset}&limit=4`)
+                assert.strictEqual(page.response.statusCode, 200)
+                assert.strictEqual(page.json.total, all.json.total)
+                urls.push(...page.json.results.map((r) => r.url))
+            }
+            assert.deepStrictEqual(urls, all.json.results.map((r) => r.url))
+
+            const bad = await testUtil.request(`${ctx.host}/search?q=connect&limit=many`)
+            assert.strictEqual(bad.response.statusCode, 400)
+        })
+
+        it('should explain scores when asked', async () => {
+            const result = await testUtil.request(`${ctx.host}/search?q=compass&explain=true`)
+            assert.strictEqual(result.response.statusCode, 200)
+
+            const explain = result.json.results[0].explain
+            assert.deepStrictEqual(Object.keys(explain).sort(), ['authority', 'boost', 'hub', 'penalty', 'proximity', 'relevancy', 'score', 'terms'])
+            assert.strictEqual(explain.boost, 1)
+            assert.strictEqual(explain.terms[0].term, 'compass')
+            assert.strictEqual(explain.terms[0].weight, 1)
+            assert.ok(explain.terms[0].fields.title > 0)
+
+            const plain = await testUtil.request(`${ctx.host}/search?q=compass`)
+            assert.strictEqual(plain.json.results[0].explain, undefined)
+        })
+
+        it('should accept ranking overrides', async () => {
+            const result = await testUtil.request(`${ctx.host}/search?q=compass&explain=true&ranking=authority_weight:0,penalty_threshold:0`)
+            assert.strictEqual(result.response.statusCode, 200)
+
+            // Without authority or penalties, the most relevant result scores exactly log2(2)
+            assert.strictEqual(result.json.results[0].explain.score, 1)
+
+            const bm25 = await testUtil.request(`${ctx.host}/search?q=compass&explain=true&scorer=bm25`)
+            assert.strictEqual(bm25.response.statusCode, 200)
+            assert.ok(bm25.json.results.length > 0)
+            assert.notDeepStrictEqual(bm25.json.results[0].explain.terms, result.json.results[0].explain.terms)
+
+            for (const params of ['ranking=bogus:1', 'scorer=bogus']) {
+                const invalid = await testUtil.request(`${ctx.host}/search?q=compass&${params}`)
+                assert.strictEqual(invalid.response.statusCode, 400)
+            }
+        })
+
+        it('should enforce required and excluded terms', async () => {
+            const search = async (q) => {
+                const result = await testUtil.request(`${ctx.host}/search?q=${encodeURIComponent(q)}`)
+                assert.strictEqual(result.response.statusCode, 200)
+                return result.json.results.map((r) => r.url)
+            }
+
+            const all = await search('aggregation')
+            const withoutAtlas = await search('aggregation -atlas')
+            assert.ok(withoutAtlas.length > 0 && withoutAtlas.length < all.length)
+            assert.ok(withoutAtlas.every((url) => !url.includes('atlas')))
+
+            const both = await search('aggregation AND schema')
+            assert.ok(both.length > 0 && both.length < all.length)
+            assert.deepStrictEqual(await search('+aggregation +schema'), both)
+
+            assert.deepStrictEqual(await search('-aggregation'), [])
+        })
+
+        it('should restrict field-scoped terms to their field', async () => {
+            const result = await testUtil.request(`${ctx.host}/search?q=${encodeURIComponent('title:compass')}&explain=true`)
+            assert.strictEqual(result.response.statusCode, 200)
+            assert.strictEqual(result.json.results.length, 1)
+            for (const {title, explain} of result.json.results) {
+                assert.ok(title.toLowerCase().includes('compass'))
+                for (const term of explain.terms) {
+                    assert.deepStrictEqual(Object.keys(term.fields), ['title'])
+                }
+            }
+        })
+
+        it('should only expand prefixes as configured', async () => {
+            const total = async (q, ranking) => {
+                const result = await testUtil.request(`${ctx.host}/search?q=${encodeURIComponent(q)}&ranking=${ranking}`)
+                assert.strictEqual(result.response.statusCode, 200)
+                return result.json.total
+            }
+
+            const expanded = await total('aggre', '')
+            assert.ok(expanded > 0)
+            assert.strictEqual(await total('aggre', 'prefix_expansion:none'), 0)
+            assert.strictEqual(await total('aggre', 'prefix_expansion:last'), expanded)
+            assert.strictEqual(await total('aggre ', 'prefix_expansion:last'), 0)
+            assert.strictEqual(await total('aggre', 'prefix_min_length:6'), 0)
+        })
+
+        it('should suggest completions', async () => {
+            let result = await testUtil.request(`${ctx.host}/suggest?q=${encodeURIComponent('how to agg')}`)
+            assert.strictEqual(result.response.statusCode, 200)
+            assert.strictEqual(result.response.headers['content-type'], 'application/json')
+            assert.strictEqual(result.json.completions[0], 'how to aggregation')
+            assert.ok(result.json.completions.every((completion) => completion.startsWith('how to agg')))
+
+            result = await testUtil.request(`${ctx.host}/suggest?q=conn&searchProperty=atlas-master&limit=3`)
+            assert.strictEqual(result.response.statusCode, 200)
+            assert.ok(result.json.completions.length <= 3)
+            assert.ok(result.json.titles.length > 0)
+            for (const {title, url} of result.json.titles) {
+                assert.ok(title.toLowerCase().includes('conn'))
+                assert.ok(url.startsWith('https://docs.atlas.mongodb.com/'))
+            }
+
+            result = await testUtil.request(`${ctx.host}/suggest`)
+            assert.strictEqual(result.response.statusCode, 400)
+        })
+
+        it('should suggest spelling corrections', async () => {
+            const result = await testUtil.request(`${ctx.host}/search?q=${encodeURIComponent('quary compass')}`)
+            assert.strictEqual(result.response.statusCode, 200)
+            assert.deepStrictEqual(result.json.spellingCorrections, {'quary': 'query'})
+        })
+
+        it('should fall back to fuzzy matches for misspelled terms', async () => {
+            let result = await testUtil.request(`${ctx.host}/search?q=quary`)
+            assert.strictEqual(result.response.statusCode, 200)
+            assert.deepStrictEqual(result.json.fuzzyMatches, {'quary': ['query']})
+            assert.ok(result.json.total > 0)
+
+            result = await testUtil.request(`${ctx.host}/search?q=quary&ranking=fuzzy_weight:0`)
+            assert.strictEqual(result.json.fuzzyMatches, undefined)
+            assert.strictEqual(result.json.total, 0)
+        })
+
+        it('should count matches in each search property', async () => {
+            let result = await testUtil.request(`${ctx.host}/search?q=aggregation&facets=true`)
+            assert.strictEqual(result.response.statusCode, 200)
+            const facets = result.json.facets
+            assert.ok(facets['atlas-master'] > 0)
+            assert.ok(facets['bi-connector-master'] > 0)
+            assert.strictEqual(Object.values(facets).reduce((a, b) => a + b), result.json.total)
+
+            result = await testUtil.request(`${ctx.host}/search?q=aggregation&searchProperty=atlas-master&facets=true`)
+            assert.deepStrictEqual(Object.keys(result.json.facets), ['atlas-master'])
+
+            result = await testUtil.request(`${ctx.host}/search?q=aggregation`)
+            assert.strictEqual(result.json.facets, undefined)
+        })
+
+        it('should filter on document metadata', async () => {
+            let result = await testUtil.request(`${ctx.host}/search?q=aggregation&filter=product:atlas`)
+            assert.strictEqual(result.response.statusCode, 200)
+            assert.strictEqual(result.json.total, 0)
+
+            result = await testUtil.request(`${ctx.host}/search?q=aggregation&filter=product`)
+            assert.strictEqual(result.response.statusCode, 400)
+
+            // Long filters don't count against the length of the query itself
+            const filter = Array.from({length: 10}, (_, i) => `product:product-${i}`).join(',')
+            result = await testUtil.request(`${ctx.host}/search?q=aggregation&filter=${filter}&explain=true&ranking=authority_weight:0`)
+            assert.strictEqual(result.response.statusCode, 200)
+
+            result = await testUtil.request(`${ctx.host}/search?q=${'aggregation '.repeat(10)}`)
+            assert.strictEqual(result.response.statusCode, 400)
+        })
+
+        it('should pin results for curated queries', async () => {
+            let result = await testUtil.request(`${ctx.host}/search?q=${encodeURIComponent('Connection Strings')}`)
+            assert.strictEqual(result.response.statusCode, 200)
+            assert.deepStrictEqual(result.json.results.slice(0, 2).map((r) => [r.url, r.pinned]), [
+                ['https://docs.mongodb.com/bi-connector/current/connect/tableau/', true],
+                ['https://docs.atlas.mongodb.com/driver-connection/', true]])
+            assert.ok(result.json.results.slice(2).every((r) => r.pinned === undefined))
+
+            result = await testUtil.request(`${ctx.host}/search?q=${encodeURIComponent('connection string')}&searchProperty=atlas-master`)
+            assert.strictEqual(result.json.results[0].url, 'https://docs.atlas.mongodb.com/driver-connection/')
+            assert.strictEqual(result.json.results[0].pinned, true)
+            assert.strictEqual(result.json.results[1].pinned, undefined)
+
+            result = await testUtil.request(`${ctx.host}/status`)
+            assert.deepStrictEqual(result.json.pinned, {'connect string': [
+                'https://docs.mongodb.com/bi-connector/current/connect/tableau/',
+                'https://docs.atlas.mongodb.com/driver-connection/']})
+        })
+
+        it('should return snippets when asked', async () => {
+            const result = await testUtil.request(`${ctx.host}/search?q=compass&searchProperty=atlas-master&snippets=true`)
+            assert.strictEqual(result.response.statusCode, 200)
+
+            const first = result.json.results[0]
+            assert.strictEqual(first.url, 'https://docs.atlas.mongodb.com/compass-connection/')
+            assert.ok(first.matches.includes('compass'))
+            assert.ok(first.snippet.highlights.length > 0)
+            for (const [start, end] of first.snippet.highlights) {
+                assert.strictEqual(Array.from(first.snippet.text).slice(start, end).join('').toLowerCase(), 'compass')
+            }
+        })
+
         it('should return 304 if index hasn\'t changed', async () => {
             const result = await testUtil.request(`${ctx.host}/search?q=${encodeURIComponent('quary')}`, {
                 headers: {
@@ -101,6 +298,36 @@ describe('integration', function() {
         })
     })
 
+    it('should coalesce concurrent refreshes', async function() {
+        this.slow(5000)
+
+        const results = await Promise.all([
+            testUtil.request(`${ctx.host}/refresh`, {method: 'POST'}),
+            testUtil.request(`${ctx.host}/refresh`, {method: 'POST'})
+        ])
+        assert.strictEqual(results[0].response.statusCode, 200)
+        assert.strictEqual(results[1].response.statusCode, 200)
+        assert.strictEqual(results[0].json.id, results[1].json.id)
+        assert.strictEqual(results[0].json.state, 'succeeded')
+
+        const job = await testUtil.request(`${ctx.host}/refresh/${results[0].json.id}`)
+        assert.strictEqual(job.response.statusCode, 200)
+        assert.deepStrictEqual(job.json, {'id': results[0].json.id, 'state': 'succeeded'})
+    })
+
+    it('should return 202 to /refresh when asked to respond asynchronously', async function() {
+        this.slow(5000)
+
+        const result = await testUtil.request(`${ctx.host}/refresh`, {
+            method: 'POST',
+            headers: {'Prefer': 'respond-async'}})
+        assert.strictEqual(result.response.statusCode, 202)
+        assert.strictEqual(result.response.headers['location'], `/refresh/${result.json.id}`)
+
+        const missing = await testUtil.request(`${ctx.host}/refresh/0`)
+        assert.strictEqual(missing.response.statusCode, 404)
+    })
+
     after('shutting down', function() {
         process.kill(ctx.child.pid, 'SIGINT')
     })
@@ -4,10 +4,10 @@ const child_process = require('child_process')
 const http = require('http')
 const readline = require('readline')
 
-function startServer(serverPath, manifestPath, done) {
+function startServer(serverPath, args, done) {
     let isDone = false
 
-    const child = child_process.spawn(serverPath, [manifestPath], {
+    const child = child_process.spawn(serverPath, args, {
         stdio: [0, 1, 'pipe']
     })
 